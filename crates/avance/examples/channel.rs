@@ -0,0 +1,25 @@
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use avance::AvanceIterator;
+
+fn main() {
+    let (tx, rx) = mpsc::channel();
+
+    for worker in 0..4 {
+        let tx = tx.clone();
+        thread::spawn(move || {
+            for job in 0..250 {
+                thread::sleep(Duration::from_millis(5));
+                tx.send((worker, job)).unwrap();
+            }
+        });
+    }
+    // The channel closes once all workers are done
+    drop(tx);
+
+    // The number of results is unknown, so the bar is unbounded
+    let results = rx.avance().with_desc("results").count();
+    assert_eq!(results, 1000);
+}
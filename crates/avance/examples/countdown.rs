@@ -0,0 +1,14 @@
+use std::thread;
+use std::time::Duration;
+
+use avance::AvanceBar;
+
+fn main() {
+    let pb = AvanceBar::timer(Duration::from_secs(5))
+        .with_countdown(true)
+        .with_desc("countdown");
+
+    while !pb.tick_by_time() {
+        thread::sleep(Duration::from_millis(50));
+    }
+}
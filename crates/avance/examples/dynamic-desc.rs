@@ -0,0 +1,20 @@
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use avance::AvanceBar;
+
+fn main() {
+    let files: Vec<_> = (0..200).map(|i| format!("file{:03}.txt", i)).collect();
+    let current = Arc::new(Mutex::new(String::new()));
+
+    let shown = Arc::clone(&current);
+    let pb = AvanceBar::new(files.len() as u64)
+        .with_dynamic_desc(move || shown.lock().unwrap().clone().into());
+
+    for file in pb.with_iter(&files) {
+        // Cheap to update on every item, as it's only read when the bar is redrawn
+        *current.lock().unwrap() = file.clone();
+        thread::sleep(Duration::from_millis(20));
+    }
+}
@@ -0,0 +1,21 @@
+use std::thread;
+use std::time::Duration;
+
+use avance::AvanceBar;
+
+fn main() {
+    let files = AvanceBar::new(10).with_desc("files");
+
+    for i in 0..10 {
+        // Each file advances the outer bar once its bar is closed
+        let bytes = files
+            .child(1024 * (i + 1))
+            .with_desc(format!("file{}", i))
+            .with_unit_scale(true);
+        for _ in bytes.with_iter(0..1024 * (i + 1)) {
+            thread::sleep(Duration::from_micros(100));
+        }
+    }
+
+    assert_eq!(files.update_and_get(0), 10);
+}
@@ -0,0 +1,42 @@
+use std::thread;
+use std::time::Duration;
+
+use avance::{track, ProgressSource};
+
+/// A mock paginated API, which tells how many items are left
+struct Api {
+    fetched: u64,
+    total: u64,
+}
+
+impl Api {
+    fn next_page(&mut self) -> Option<Vec<u64>> {
+        thread::sleep(Duration::from_millis(100));
+
+        if self.fetched >= self.total {
+            return None;
+        }
+        let page: Vec<_> = (self.fetched..self.total.min(self.fetched + 20)).collect();
+        self.fetched += page.len() as u64;
+        Some(page)
+    }
+}
+
+impl ProgressSource for Api {
+    fn remaining(&self) -> Option<u64> {
+        Some(self.total - self.fetched)
+    }
+}
+
+fn main() {
+    let mut api = track(Api {
+        fetched: 0,
+        total: 500,
+    });
+    api.pb().set_desc("fetching");
+
+    while let Some(page) = api.with_mut(|api| api.next_page()) {
+        api.pb()
+            .set_postfix(format!("last item: {}", page[page.len() - 1]));
+    }
+}
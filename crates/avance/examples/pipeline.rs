@@ -0,0 +1,26 @@
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use avance::{AvanceBar, LinkedBars};
+
+fn main() {
+    // At most 16 items are buffered between the producer and the consumer
+    let (tx, rx) = mpsc::sync_channel(16);
+    let producer = AvanceBar::new(500).with_desc("produced");
+    let consumer = AvanceBar::new(500).with_desc("consumed");
+    let backlog = LinkedBars::new(&producer, &consumer, 16);
+
+    thread::spawn(move || {
+        for item in producer.with_iter(0..500) {
+            thread::sleep(Duration::from_millis(4));
+            tx.send(item).unwrap();
+        }
+    });
+
+    // The consumer is slower, so the backlog fills up
+    for _ in consumer.with_iter(rx) {
+        thread::sleep(Duration::from_millis(6));
+        backlog.refresh();
+    }
+}
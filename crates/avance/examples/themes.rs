@@ -0,0 +1,24 @@
+use std::thread;
+use std::time::Duration;
+
+use avance::{AvanceBar, Style, Theme};
+
+fn main() {
+    std::thread::scope(|t| {
+        for (theme, style) in [
+            (Theme::Default, Style::ASCII),
+            (Theme::Nord, Style::Block),
+            (Theme::Solarized, Style::Block),
+            (Theme::Monochrome, Style::Balloon),
+        ] {
+            t.spawn(move || {
+                AvanceBar::new(1000)
+                    .with_style(style)
+                    .with_theme(theme)
+                    .with_desc(format!("{:?}", theme))
+                    .with_iter(0..1000)
+                    .for_each(|_| thread::sleep(Duration::from_millis(5)));
+            });
+        }
+    });
+}
@@ -1,20 +1,26 @@
 //! A progress bar and all utilities.
 
-use crossterm::cursor::{MoveToColumn, MoveUp};
-use crossterm::style::Print;
+use crossterm::cursor::{MoveTo, MoveToColumn, MoveUp, RestorePosition, SavePosition};
+use crossterm::style::{Color, Print, Stylize};
 use crossterm::terminal::{self, Clear, ClearType};
 use crossterm::tty::IsTty;
 use crossterm::QueueableCommand;
 use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
 use std::cmp::{max, min};
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::fmt::{Display, Formatter};
-use std::io::{stderr, Result, Write};
+use std::fs::File;
+use std::io::{stderr, stdout, Result, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 use std::sync::{
-    atomic::{AtomicU16, AtomicU64, Ordering},
-    Arc, Mutex,
+    atomic::{
+        AtomicBool, AtomicI32, AtomicI64, AtomicU16, AtomicU32, AtomicU64, AtomicU8, Ordering,
+    },
+    Arc, Mutex, Weak,
 };
-use std::time::Instant;
+use std::time::{Duration, Instant, SystemTime};
 
 #[cfg(has_std_once_cell = "false")]
 use once_cell::sync::OnceCell as OnceLock;
@@ -22,23 +28,206 @@ use once_cell::sync::OnceCell as OnceLock;
 use std::sync::OnceLock;
 
 use super::*;
+use crate::clock::Clock;
+use crate::observer::{self, Event, Snapshot};
+use crate::testing::{self, Output};
 
 /// The progress bar
+///
+/// # Thread safety
+///
+/// A progress bar is `Send + Sync`, and cloning it only gives another handle of the
+/// same bar, so it can be shared among threads either by cloning or by reference.
+/// Updates from different threads are never lost, and the bar is drawn by one thread
+/// at a time. User-provided code (such as conversions of descriptions and postfixes)
+/// is never run while the bar is locked, so a panic in it can't poison the bar.
+/// The only exception is the closure of [`set_dynamic_desc`](Self::set_dynamic_desc),
+/// which is evaluated on each render with the bar locked.
 #[derive(Debug, Clone)]
 pub struct AvanceBar {
     state: AtomicState,
     progress: Arc<AtomicProgress>,
 }
 
+// Fails to compile if a progress bar can't be shared among threads
+const _: fn() = || {
+    fn assert<T: Send + Sync>() {}
+    assert::<AvanceBar>();
+};
+
 // Public Interface
 impl AvanceBar {
     /// Create a new progress bar
     pub fn new(total: u64) -> Self {
-        let progress = Arc::new(AtomicProgress::new());
-        let pb = AvanceBar {
-            state: Arc::new(Mutex::new(State::new(Some(total), Arc::clone(&progress)))),
-            progress,
-        };
+        let pb = Self::from_total(Some(total));
+        pb.refresh();
+        pb
+    }
+
+    /// Create a progress bar driven by time rather than by items, whose total
+    /// is the given duration in milliseconds.
+    ///
+    /// Call [`tick_by_time`](Self::tick_by_time) to catch up with the time elapsed
+    /// since the bar was created.
+    ///
+    /// # Examples
+    /// ```
+    /// # use avance::AvanceBar;
+    /// # use std::time::Duration;
+    /// let pb = AvanceBar::timer(Duration::from_millis(300)).with_desc("waiting");
+    /// while !pb.tick_by_time() {
+    ///     std::thread::sleep(Duration::from_millis(50));
+    /// }
+    /// ```
+    pub fn timer(duration: Duration) -> Self {
+        Self::new(duration.as_millis().into_total())
+    }
+
+    /// Create a progress bar whose total is the length of a range, along with
+    /// an iterator over the range advancing it.
+    ///
+    /// # Examples
+    /// ```
+    /// # use avance::AvanceBar;
+    /// let (pb, iter) = AvanceBar::for_range(0..1000);
+    /// pb.set_desc("range");
+    /// for _ in iter {
+    ///     // ...
+    /// }
+    /// ```
+    pub fn for_range<T>(range: Range<T>) -> (Self, AvanceIter<Range<T>>)
+    where
+        Range<T>: Iterator,
+    {
+        let pb = Self::from_total(range.size_hint().1.map(|len| len.into_total()));
+        let iter = pb.with_iter(range);
+        (pb, iter)
+    }
+
+    /// Create a progress bar whose total is the length of a slice, along with
+    /// an iterator over the slice advancing it.
+    ///
+    /// # Examples
+    /// ```
+    /// # use avance::AvanceBar;
+    /// let data = vec![1, 2, 3];
+    /// let (pb, iter) = AvanceBar::for_slice(&data);
+    /// pb.set_desc("slice");
+    /// for _ in iter {
+    ///     // ...
+    /// }
+    /// ```
+    pub fn for_slice<T>(slice: &[T]) -> (Self, AvanceIter<std::slice::Iter<'_, T>>) {
+        let pb = Self::new(slice.len().into_total());
+        let iter = pb.with_iter(slice);
+        (pb, iter)
+    }
+
+    /// Create a progress bar for reading a file, whose total is the size of the file
+    /// in bytes, shown with binary prefixes like `1.50MiB`. Write the bytes being read
+    /// to the bar to advance it.
+    ///
+    /// The bar is unbounded if the size is unknown, such as for pipes, or for files
+    /// which report a size of 0 like those in `/proc`.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # use avance::AvanceBar;
+    /// # use std::fs::File;
+    /// let mut file = File::open("data.bin").unwrap();
+    /// let pb = AvanceBar::for_file(&file).with_desc("reading");
+    /// std::io::copy(&mut file, &mut &pb).unwrap();
+    /// ```
+    pub fn for_file(file: &File) -> Self {
+        let total = file
+            .metadata()
+            .ok()
+            .filter(|meta| meta.is_file() && meta.len() > 0)
+            .map(|meta| meta.len());
+        let pb = Self::from_total(total);
+        {
+            let mut state = pb.state.lock().unwrap();
+            state.template.unit_scale = true;
+            state.template.binary_units = true;
+        }
+        pb.refresh();
+        pb
+    }
+
+    /// Create a progress bar of 100 steps, which is meant to be driven by
+    /// [`set_fraction`](Self::set_fraction), such as when the progress is
+    /// reported as a fraction by another library.
+    ///
+    /// # Examples
+    /// ```
+    /// # use avance::AvanceBar;
+    /// let pb = AvanceBar::new_percentage();
+    /// pb.set_fraction(0.42);
+    /// # #[cfg(not(feature = "disabled"))]
+    /// assert!(pb.render_line(80).starts_with(" 42%|"));
+    /// ```
+    pub fn new_percentage() -> Self {
+        Self::new(100)
+    }
+
+    /// Create a progress bar which tracks a counter owned by someone else,
+    /// such as code that doesn't hold a bar. The counter is read as the progress
+    /// whenever the bar is refreshed, by [`update(0)`](Self::update),
+    /// [`pump`](Self::pump) or [`refresh`](Self::refresh).
+    ///
+    /// The bar shouldn't be advanced by itself, since the counter overrides it.
+    ///
+    /// # Examples
+    /// ```
+    /// # use avance::AvanceBar;
+    /// # use std::sync::{atomic::{AtomicU64, Ordering}, Arc};
+    /// let counter = Arc::new(AtomicU64::new(0));
+    /// let pb = AvanceBar::tracking(100, Arc::clone(&counter));
+    /// std::thread::scope(|t| {
+    ///     t.spawn(|| (0..100).for_each(|_| {
+    ///         counter.fetch_add(1, Ordering::Relaxed);
+    ///     }));
+    /// });
+    /// pb.refresh();
+    /// ```
+    pub fn tracking(total: u64, counter: Arc<AtomicU64>) -> Self {
+        let pb = Self::from_total(Some(total));
+        let _ = pb.progress.source.set(counter);
+        pb.refresh();
+        pb
+    }
+
+    /// Create a progress bar from a set of options, which may be kept in
+    /// the settings of an application.
+    ///
+    /// # Examples
+    /// ```
+    /// # use avance::{AvanceBar, BarOptions, Style};
+    /// let options = BarOptions {
+    ///     total: Some(100),
+    ///     style: Some(Style::Block),
+    ///     desc: Some("task".into()),
+    ///     ..Default::default()
+    /// };
+    /// let pb = AvanceBar::from_config(options);
+    /// ```
+    pub fn from_config(options: BarOptions) -> Self {
+        let pb = Self::from_total(options.total);
+        {
+            let mut state = pb.state.lock().unwrap();
+            let template = &mut state.template;
+            if let Some(style) = options.style {
+                template.style = style;
+            }
+            if options.width.is_some() {
+                template.width = options.width;
+            }
+            if options.desc.is_some() {
+                template.desc = options.desc;
+            }
+            template.postfix = options.postfix;
+            template.unit_scale = options.unit_scale;
+        }
         pb.refresh();
         pb
     }
@@ -66,7 +255,34 @@ impl AvanceBar {
         self
     }
 
-    /// Wrap an iterator to display its progress.
+    /// Create `n` progress bars with the template and the length of this one, each
+    /// with its own progress, such as for showing one bar per worker. Unlike clones,
+    /// which are handles of the same bar, they're advanced independently.
+    ///
+    /// The bars are placed one after another, unless other threads create
+    /// bars at the same time.
+    ///
+    /// # Examples
+    /// ```
+    /// # use avance::{AvanceBar, Style};
+    /// let template = AvanceBar::new(100).with_style(Style::Block).with_desc("worker");
+    /// std::thread::scope(|t| {
+    ///     for pb in template.spawn_group(4) {
+    ///         t.spawn(move || pb.with_iter(0..100).for_each(|_| {}));
+    ///     }
+    /// });
+    /// ```
+    pub fn spawn_group(&self, n: usize) -> Vec<AvanceBar> {
+        let total = self.total();
+        (0..n)
+            .map(|_| AvanceBar::from_total(total).with_template_of(self))
+            .collect()
+    }
+
+    /// Wrap an iterator (or anything can be turned into an iterator) to display its progress.
+    ///
+    /// If the total of the progress bar is unknown, the upper bound of the iterator's
+    /// size will be used as the total.
     ///
     /// See another way of progressing with an iterator at [`AvancesIterator`](crate::AvanceIterator)
     ///
@@ -77,14 +293,52 @@ impl AvanceBar {
     /// for _ in pb.with_iter(0..100) {
     ///     // ...
     /// }
+    ///
+    /// let v = vec![1, 2, 3];
+    /// let pb = AvanceBar::new(3);
+    /// for _ in pb.with_iter(&v) {
+    ///     // ...
+    /// }
     /// ```
-    pub fn with_iter<Iter: Iterator>(&self, iter: Iter) -> AvanceIter<Iter> {
+    pub fn with_iter<I: IntoIterator>(&self, iter: I) -> AvanceIter<I::IntoIter> {
+        let iter = iter.into_iter();
+
+        let mut state = self.state.lock().unwrap();
+        if state.total.is_none() {
+            state.set_total(iter.size_hint().1.map(|s| s as u64));
+            let _ = state.draw_to_stderr(None);
+        }
+        let warning = state.plan(iter.size_hint().0 as u64);
+        drop(state);
+
+        if let Some(warning) = warning {
+            eprint_above(warning);
+        }
+
         AvanceIter {
             iter,
             bar: self.clone(),
+            exhausted: false,
         }
     }
 
+    /// Builder-like function for a progress bar with a given total length,
+    /// which accepts any primitive integer.
+    ///
+    /// Negative totals are treated as 0, and totals beyond [`u64::MAX`] are
+    /// saturated to it.
+    ///
+    /// # Examples
+    /// ```
+    /// # use avance::AvanceBar;
+    /// let v = vec![0; 100];
+    /// let pb = AvanceBar::new(0).with_total(v.len());
+    /// ```
+    pub fn with_total(self, total: impl Total) -> Self {
+        self.set_total(Some(total.into_total()));
+        self
+    }
+
     /// Builder-like function for a progress bar with a given style
     /// (default: [`Style::ASCII`]).
     ///
@@ -113,8 +367,12 @@ impl AvanceBar {
     /// ```
     /// # use avance::AvanceBar;
     /// let pb = AvanceBar::new(1000).with_style_str("=>-");
+    ///
+    /// // Styles computed at runtime are accepted as well
+    /// let fill = if cfg!(windows) { "#" } else { "=" };
+    /// let pb = AvanceBar::new(1000).with_style_str(format!("{}>-", fill));
     /// ```
-    pub fn with_style_str(self, s: &'static str) -> Self {
+    pub fn with_style_str(self, s: impl Into<Cow<'static, str>>) -> Self {
         self.set_style_str(s);
         self
     }
@@ -155,487 +413,5106 @@ impl AvanceBar {
         self
     }
 
-    /// Override the postfix of a progress bar.
+    /// Builder-like function for a progress bar counting down the remaining steps.
     ///
-    /// Postfix is usually used for **dynamically** displaying some
-    /// additional information, such as the accuracy when training a model.
+    /// See [`set_countdown`](Self::set_countdown)
     ///
-    /// See [`AvanceIter::with_pb`] if you want to change the postfix when
-    /// progressing with an iterator.
-    pub fn set_postfix(&self, postfix: impl Into<Cow<'static, str>>) {
-        let mut state = self.state.lock().unwrap();
-        state.template.postfix = Some(postfix.into());
-        let _ = state.draw_to_stderr(None);
-    }
-
-    /// Advance the progress bar by n steps.
-    pub fn update(&self, n: u64) {
-        self.progress.inc(n);
-
-        if self.progress.ready() {
-            let _ = self.state.lock().unwrap().draw_to_stderr(None);
-            self.progress.update();
-        }
+    /// # Examples
+    /// ```
+    /// # use avance::AvanceBar;
+    /// let pb = AvanceBar::new(100).with_countdown(true);
+    /// pb.update(60);
+    /// # #[cfg(not(feature = "disabled"))]
+    /// assert!(pb.render_line(80).starts_with(" 40%|"));
+    /// ```
+    pub fn with_countdown(self, countdown: bool) -> Self {
+        self.set_countdown(countdown);
+        self
     }
 
-    /// Advance the progress bar by one step, with the same effect as
-    /// [`update(1)`](Self::update). If you don't want to invoke inc
-    /// manually, see another method at [`with_iter`](Self::with_iter).
+    /// Builder-like function for a progress bar showing when it will be done,
+    /// rather than how long it will take.
+    ///
+    /// See [`set_eta_absolute`](Self::set_eta_absolute)
     ///
     /// # Examples
     /// ```
     /// # use avance::AvanceBar;
-    /// let pb = AvanceBar::new(1000);
-    /// for _ in 0..1000 {
-    ///     // ...
-    ///     pb.inc();
-    /// }
+    /// let pb = AvanceBar::new(100).with_eta_absolute(true);
+    /// pb.update(50);
+    /// // e.g. " 50%|█████     | 50/100 [00:00<@14:32, 2.00Mit/s]"
+    /// # #[cfg(not(feature = "disabled"))]
+    /// assert!(pb.render_line(80).contains("<@"));
     /// ```
-    pub fn inc(&self) {
-        self.update(1);
+    pub fn with_eta_absolute(self, eta_absolute: bool) -> Self {
+        self.set_eta_absolute(eta_absolute);
+        self
     }
 
-    /// Manually stop the progress bar, and leave the current progress on terminal.
-    /// Usually users don't have to call this method directly, as a progress bar will
-    /// be closed automatically when dropped.
+    /// Builder-like function for a progress bar displaying the raw instant rate,
+    /// which is useful when benchmarking.
     ///
-    /// Users should close a bar manually when they want to preserve the rendering order
-    /// of progress bars, otherwise, progress bars will be closed in the order of being
-    /// dropped (Closing order is the same as the rendering order).
-    pub fn close(&self) {
-        let _ = self.state.lock().unwrap().close();
+    /// See [`set_raw_rate`](Self::set_raw_rate)
+    pub fn with_raw_rate(self, raw_rate: bool) -> Self {
+        self.set_raw_rate(raw_rate);
+        self
     }
 
-    /// Set the style (default: [`Style::ASCII`]) of a progress bar.
-    pub fn set_style(&self, style: Style) {
-        let mut state = self.state.lock().unwrap();
-        state.template.style = style;
-        let _ = state.draw_to_stderr(None);
+    /// Builder-like function for a progress bar with a color theme
+    /// (default: [`Theme::Default`]).
+    ///
+    /// Colors are only applied when drawing to a terminal.
+    ///
+    /// # Examples
+    /// ```
+    /// # use avance::{AvanceBar, Theme};
+    /// let pb = AvanceBar::new(1000).with_theme(Theme::Nord);
+    /// ```
+    pub fn with_theme(self, theme: Theme) -> Self {
+        self.set_theme(theme);
+        self
     }
 
-    /// Set the user-custom style of a progress bar.
-    pub fn set_style_str(&self, s: impl Into<Cow<'static, str>>) {
-        let mut state = self.state.lock().unwrap();
-        state.template.style = Style::Custom(s.into());
-        let _ = state.draw_to_stderr(None);
+    /// Builder-like function for a progress bar redrawing on every update.
+    ///
+    /// See [`set_refresh_on_inc`](Self::set_refresh_on_inc)
+    ///
+    /// # Examples
+    /// ```
+    /// # use avance::AvanceBar;
+    /// let pb = AvanceBar::new(100).with_refresh_on_inc(true);
+    /// ```
+    pub fn with_refresh_on_inc(self, refresh_on_inc: bool) -> Self {
+        self.set_refresh_on_inc(refresh_on_inc);
+        self
     }
 
-    /// Set a progress bar's width
-    pub fn set_width(&self, width: u16) {
-        let mut state = self.state.lock().unwrap();
-        state.template.width = Some(width);
-        let _ = state.clear();
-        let _ = state.draw_to_stderr(None);
+    /// Builder-like function for a progress bar redrawn at most `hz` times per second.
+    ///
+    /// See [`set_refresh_rate`](Self::set_refresh_rate)
+    ///
+    /// # Examples
+    /// ```
+    /// # use avance::AvanceBar;
+    /// // Smooth enough for screen recordings
+    /// let pb = AvanceBar::new(100).with_refresh_rate(60.0);
+    /// ```
+    pub fn with_refresh_rate(self, hz: f64) -> Self {
+        self.set_refresh_rate(hz);
+        self
     }
 
-    /// Set the description (prefix) of a progress bar.
-    pub fn set_desc(&self, desc: impl Into<Cow<'static, str>>) {
-        let mut state = self.state.lock().unwrap();
-        state.template.desc = Some(desc.into());
-        let _ = state.draw_to_stderr(None);
+    /// Builder-like function for a progress bar redrawn more often near completion.
+    ///
+    /// See [`set_adaptive_refresh`](Self::set_adaptive_refresh)
+    ///
+    /// # Examples
+    /// ```
+    /// # use avance::AvanceBar;
+    /// let pb = AvanceBar::new(100).with_adaptive_refresh(true);
+    /// ```
+    pub fn with_adaptive_refresh(self, adaptive_refresh: bool) -> Self {
+        self.set_adaptive_refresh(adaptive_refresh);
+        self
     }
 
-    /// If unit_scale (default: false) is set true, prints the number of iterations
-    /// with an appropriate SI metric prefix.
-    pub fn set_unit_scale(&self, unit_scale: bool) {
-        self.state.lock().unwrap().template.unit_scale = unit_scale;
+    /// Builder-like function for a progress bar hidden some time after it's complete.
+    ///
+    /// See [`set_hide_after_complete`](Self::set_hide_after_complete)
+    ///
+    /// # Examples
+    /// ```
+    /// # use avance::AvanceBar;
+    /// # use std::time::Duration;
+    /// let pb = AvanceBar::new(100).with_hide_after_complete(Duration::from_secs(3));
+    /// ```
+    pub fn with_hide_after_complete(self, after: Duration) -> Self {
+        self.set_hide_after_complete(Some(after));
+        self
     }
-}
 
-// Private Interface
-impl AvanceBar {
-    /// Creates a progress bar from an iterator's size hint
-    pub(crate) fn with_hint(size_hint: Option<usize>) -> Self {
-        let progress = Arc::new(AtomicProgress::new());
-        AvanceBar {
-            state: Arc::new(Mutex::new(State::new(
-                size_hint.map(|s| s as u64),
-                Arc::clone(&progress),
-            ))),
-            progress,
-        }
+    /// Builder-like function for a progress bar closed once it reaches its total.
+    ///
+    /// See [`set_auto_close`](Self::set_auto_close)
+    ///
+    /// # Examples
+    /// ```
+    /// # use avance::AvanceBar;
+    /// let pb = AvanceBar::new(100).with_auto_close(true);
+    /// pb.update(100);
+    /// // Closed already, leaving its final line
+    /// ```
+    pub fn with_auto_close(self, auto_close: bool) -> Self {
+        self.set_auto_close(auto_close);
+        self
     }
 
-    /// Refresh the progress bar.
-    fn refresh(&self) {
-        let state = self.state.lock().unwrap();
-        let _ = state.draw_to_stderr(None);
+    /// Builder-like function for a progress bar checking that it will reach its total.
+    ///
+    /// See [`set_verify_total`](Self::set_verify_total)
+    ///
+    /// # Examples
+    /// ```
+    /// # use avance::AvanceBar;
+    /// let v = vec![0; 100];
+    /// let pb = AvanceBar::new(100).with_verify_total(true);
+    /// for chunk in v.chunks(30) {
+    ///     for _ in pb.with_iter(chunk) {
+    ///         // ...
+    ///     }
+    /// }
+    /// ```
+    pub fn with_verify_total(self, verify_total: bool) -> Self {
+        self.set_verify_total(verify_total);
+        self
     }
-}
 
-#[derive(Debug)]
-struct State {
-    id: ID,
-    progress: Arc<AtomicProgress>,
-    template: Template,
-    total: Option<u64>,
-}
+    /// Builder-like function for a progress bar printing lines of a fixed width
+    /// when stderr isn't a terminal.
+    ///
+    /// See [`set_ncols`](Self::set_ncols)
+    ///
+    /// # Examples
+    /// ```
+    /// # use avance::AvanceBar;
+    /// let pb = AvanceBar::new(100).with_ncols(80);
+    /// ```
+    pub fn with_ncols(self, ncols: u16) -> Self {
+        self.set_ncols(Some(ncols));
+        self
+    }
 
-impl State {
-    fn new(total: Option<u64>, progress: Arc<AtomicProgress>) -> Self {
-        Self {
-            id: next_free_pos(),
-            progress,
-            template: Template::new(),
-            total,
-        }
+    /// Builder-like function for an unbounded progress bar showing a sweeping block.
+    ///
+    /// See [`set_indeterminate`](Self::set_indeterminate)
+    ///
+    /// # Examples
+    /// ```
+    /// # use avance::AvanceBar;
+    /// let pb = AvanceBar::new(0).with_indeterminate(true);
+    /// pb.set_total(None);
+    /// ```
+    pub fn with_indeterminate(self, indeterminate: bool) -> Self {
+        self.set_indeterminate(indeterminate);
+        self
     }
 
-    fn draw<W: Write>(&self, pos: Option<u16>, target: &mut W) -> Result<()> {
-        if pos.is_none() && !self.drawable() {
-            return Ok(());
-        }
-        let pos = if let Some(pos) = pos {
+    /// Builder-like function for a progress bar calibrating how often to read the clock.
+    ///
+    /// See [`set_miniters_auto`](Self::set_miniters_auto)
+    ///
+    /// # Examples
+    /// ```
+    /// # use avance::AvanceBar;
+    /// let pb = AvanceBar::new(1_000_000).with_miniters_auto(true);
+    /// for _ in pb.with_iter(0..1_000_000) {}
+    /// ```
+    pub fn with_miniters_auto(self, miniters_auto: bool) -> Self {
+        self.set_miniters_auto(miniters_auto);
+        self
+    }
+
+    /// Builder-like function for a progress bar showing decimal places in its percentage.
+    ///
+    /// See [`set_percent_precision`](Self::set_percent_precision)
+    ///
+    /// # Examples
+    /// ```
+    /// # use avance::AvanceBar;
+    /// let pb = AvanceBar::new(1000).with_percent_precision(1);
+    /// pb.update(995);
+    /// # #[cfg(not(feature = "disabled"))]
+    /// assert!(pb.render_line(80).starts_with(" 99.5%|"));
+    /// ```
+    pub fn with_percent_precision(self, precision: u8) -> Self {
+        self.set_percent_precision(precision);
+        self
+    }
+
+    /// Builder-like function for a progress bar counting its elapsed time from `start`.
+    ///
+    /// See [`set_start_time`](Self::set_start_time)
+    ///
+    /// # Examples
+    /// ```
+    /// # use avance::AvanceBar;
+    /// use std::time::Instant;
+    ///
+    /// let job = Instant::now();
+    /// let download = AvanceBar::new(100).with_start_time(job).with_desc("download");
+    /// // ...
+    /// let extract = AvanceBar::new(100).with_start_time(job).with_desc("extract");
+    /// ```
+    pub fn with_start_time(self, start: Instant) -> Self {
+        self.set_start_time(start);
+        self
+    }
+
+    /// Builder-like function for a progress bar showing a sparkline of recent rates.
+    ///
+    /// See [`set_sparkline`](Self::set_sparkline)
+    ///
+    /// # Examples
+    /// ```
+    /// # use avance::AvanceBar;
+    /// let pb = AvanceBar::new(1000).with_sparkline(8);
+    /// ```
+    pub fn with_sparkline(self, width: usize) -> Self {
+        self.set_sparkline(width);
+        self
+    }
+
+    /// Builder-like function for a progress bar with its percentage at the given position.
+    ///
+    /// See [`set_percent_position`](Self::set_percent_position)
+    ///
+    /// # Examples
+    /// ```
+    /// # use avance::{AvanceBar, PercentPosition};
+    /// let pb = AvanceBar::new(100)
+    ///     .with_desc("task")
+    ///     .with_percent_position(PercentPosition::BeforeDesc);
+    /// pb.update(73);
+    /// # #[cfg(not(feature = "disabled"))]
+    /// assert!(pb.render_line(80).starts_with(" 73% task: |"));
+    /// ```
+    pub fn with_percent_position(self, position: PercentPosition) -> Self {
+        self.set_percent_position(position);
+        self
+    }
+
+    /// Builder-like function for a progress bar also shown in the taskbar of the terminal.
+    ///
+    /// See [`set_taskbar_progress`](Self::set_taskbar_progress)
+    ///
+    /// # Examples
+    /// ```
+    /// # use avance::AvanceBar;
+    /// let pb = AvanceBar::new(100).with_taskbar_progress(true);
+    /// ```
+    pub fn with_taskbar_progress(self, taskbar_progress: bool) -> Self {
+        self.set_taskbar_progress(taskbar_progress);
+        self
+    }
+
+    /// Builder-like function for a progress bar showing its statistics on a second line.
+    ///
+    /// See [`set_multiline`](Self::set_multiline)
+    ///
+    /// # Examples
+    /// ```
+    /// # use avance::AvanceBar;
+    /// let pb = AvanceBar::new(100).with_desc("task").with_multiline(true);
+    /// pb.update(50);
+    /// let line = pb.render_line(40);
+    /// let (bar, stats) = line.split_once('\n').unwrap();
+    /// # #[cfg(not(feature = "disabled"))]
+    /// assert!(bar.starts_with("task:  50%|"));
+    /// # #[cfg(not(feature = "disabled"))]
+    /// assert!(stats.starts_with("50/100 ["));
+    /// ```
+    pub fn with_multiline(self, multiline: bool) -> Self {
+        self.set_multiline(multiline);
+        self
+    }
+
+    /// Builder-like function for a progress bar with or without its rate.
+    ///
+    /// See [`set_show_rate`](Self::set_show_rate)
+    ///
+    /// # Examples
+    /// ```
+    /// # use avance::AvanceBar;
+    /// let pb = AvanceBar::new_percentage().with_show_rate(false);
+    /// pb.set_fraction(0.5);
+    /// # #[cfg(not(feature = "disabled"))]
+    /// assert!(pb.render_line(80).ends_with("| 50/100 [00:00<00:00]"));
+    /// ```
+    pub fn with_show_rate(self, show_rate: bool) -> Self {
+        self.set_show_rate(show_rate);
+        self
+    }
+
+    /// Builder-like function for a compact progress bar.
+    ///
+    /// See [`set_compact`](Self::set_compact)
+    ///
+    /// # Examples
+    /// ```
+    /// # use avance::{AvanceBar, Style};
+    /// let pb = AvanceBar::new(100)
+    ///     .with_style(Style::ASCII)
+    ///     .with_desc("task")
+    ///     .with_compact(true);
+    /// pb.update(50);
+    /// let line = pb.render_line(80);
+    /// # #[cfg(not(feature = "disabled"))]
+    /// assert!(line.starts_with("task:  50%|#"));
+    /// # #[cfg(not(feature = "disabled"))]
+    /// assert!(line.ends_with("| 50/100"));
+    /// ```
+    pub fn with_compact(self, compact: bool) -> Self {
+        self.set_compact(compact);
+        self
+    }
+
+    /// Builder-like function for a progress bar showing more details below it.
+    ///
+    /// See [`set_verbose`](Self::set_verbose)
+    ///
+    /// # Examples
+    /// ```
+    /// # use avance::AvanceBar;
+    /// let pb = AvanceBar::new(100).with_verbose(true);
+    /// pb.update(30);
+    /// assert_eq!(pb.render_line(80).lines().count(), 3);
+    /// ```
+    pub fn with_verbose(self, verbose: bool) -> Self {
+        self.set_verbose(verbose);
+        self
+    }
+
+    /// Builder-like function for an unbounded progress bar showing its progress
+    /// against an estimated total.
+    ///
+    /// See [`set_estimate`](Self::set_estimate)
+    ///
+    /// # Examples
+    /// ```
+    /// # use avance::AvanceBar;
+    /// let pb = AvanceBar::new(0).with_estimate(100);
+    /// pb.set_total(None);
+    /// pb.update(100);
+    /// # #[cfg(not(feature = "disabled"))]
+    /// assert!(pb.render_line(80).starts_with(" 63%|"));
+    /// ```
+    pub fn with_estimate(self, estimate: u64) -> Self {
+        self.set_estimate(Some(estimate));
+        self
+    }
+
+    /// Builder-like function for a progress bar showing its progress beyond the total
+    /// in a specific way.
+    ///
+    /// See [`set_total_overflow`](Self::set_total_overflow)
+    ///
+    /// # Examples
+    /// ```
+    /// # use avance::{AvanceBar, TotalOverflow};
+    /// let pb = AvanceBar::new(100).with_total_overflow(TotalOverflow::Unbounded);
+    /// pb.update(150);
+    /// # #[cfg(not(feature = "disabled"))]
+    /// assert!(pb.render_line(80).starts_with("150it "));
+    /// ```
+    pub fn with_total_overflow(self, overflow: TotalOverflow) -> Self {
+        self.set_total_overflow(overflow);
+        self
+    }
+
+    /// Builder-like function for a progress bar coloring its ETA by the trend.
+    ///
+    /// See [`set_smart_eta_color`](Self::set_smart_eta_color)
+    ///
+    /// # Examples
+    /// ```
+    /// # use avance::AvanceBar;
+    /// let pb = AvanceBar::new(1000).with_smart_eta_color(true);
+    /// ```
+    pub fn with_smart_eta_color(self, smart_eta_color: bool) -> Self {
+        self.set_smart_eta_color(smart_eta_color);
+        self
+    }
+
+    /// Builder-like function for a progress bar with a description evaluated on rendering.
+    ///
+    /// See [`set_dynamic_desc`](Self::set_dynamic_desc)
+    pub fn with_dynamic_desc<F>(self, desc: F) -> Self
+    where
+        F: Fn() -> Cow<'static, str> + Send + Sync + 'static,
+    {
+        self.set_dynamic_desc(desc);
+        self
+    }
+
+    /// Builder-like function for a progress bar showing an icon when it's complete.
+    ///
+    /// See [`set_success_icon`](Self::set_success_icon)
+    ///
+    /// # Examples
+    /// ```
+    /// # use avance::AvanceBar;
+    /// let pb = AvanceBar::new(100)
+    ///     .with_success_icon('✓')
+    ///     .with_fail_icon('✗');
+    /// ```
+    pub fn with_success_icon(self, icon: char) -> Self {
+        self.set_success_icon(icon);
+        self
+    }
+
+    /// Builder-like function for a progress bar showing an icon when it's
+    /// closed before complete.
+    ///
+    /// See [`set_fail_icon`](Self::set_fail_icon)
+    pub fn with_fail_icon(self, icon: char) -> Self {
+        self.set_fail_icon(icon);
+        self
+    }
+
+    /// Builder-like function for a progress bar with a minimum bar width.
+    ///
+    /// See [`set_min_bar_width`](Self::set_min_bar_width)
+    ///
+    /// # Examples
+    /// ```
+    /// # use avance::AvanceBar;
+    /// let pb = AvanceBar::new(1000).with_min_bar_width(20);
+    /// ```
+    pub fn with_min_bar_width(self, min_bar_width: u16) -> Self {
+        self.set_min_bar_width(min_bar_width);
+        self
+    }
+
+    /// Override the postfix of a progress bar.
+    ///
+    /// Postfix is usually used for **dynamically** displaying some
+    /// additional information, such as the accuracy when training a model.
+    ///
+    /// See [`AvanceIter::with_pb`] if you want to change the postfix when
+    /// progressing with an iterator.
+    pub fn set_postfix(&self, postfix: impl Into<Cow<'static, str>>) {
+        // User code runs before locking, so a panic in it can't poison the bar
+        let postfix = postfix.into();
+        let mut state = self.state.lock().unwrap();
+        state.template.postfix = Some(postfix);
+        let _ = state.draw_to_stderr(None);
+    }
+
+    /// Override the postfix of a progress bar with formatted arguments, reusing
+    /// buffers instead of allocating a new string each time.
+    ///
+    /// Prefer it over [`set_postfix`](Self::set_postfix) with [`format!`] in hot loops.
+    /// Unlike `set_postfix`, the bar isn't redrawn right away, but on the next refresh
+    /// like [`update`](Self::update), which keeps such loops fast.
+    ///
+    /// # Examples
+    /// ```
+    /// # use avance::AvanceBar;
+    /// let pb = AvanceBar::new(100);
+    /// for i in 0..100 {
+    ///     pb.set_postfix_fmt(format_args!("loss={:.3}", 1.0 / (i + 1) as f64));
+    ///     pb.inc();
+    /// }
+    /// ```
+    pub fn set_postfix_fmt(&self, args: std::fmt::Arguments) {
+        use std::fmt::Write;
+
+        let store = |formatted: &str| {
+            let mut state = self.state.lock().unwrap();
+            match &mut state.template.postfix {
+                Some(Cow::Owned(postfix)) => {
+                    postfix.clear();
+                    postfix.push_str(formatted);
+                }
+                postfix => *postfix = Some(Cow::Owned(formatted.to_string())),
+            }
+        };
+        // Formatting may run user code, which is done without locking
+        FORMATTED.with(|formatted| match formatted.try_borrow_mut() {
+            Ok(mut formatted) => {
+                formatted.clear();
+                let _ = formatted.write_fmt(args);
+                store(&formatted);
+            }
+            // Called again by the user code while formatting
+            Err(_) => store(&args.to_string()),
+        });
+        self.update(0);
+    }
+
+    /// Remove the postfix of a progress bar.
+    pub fn clear_postfix(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.template.postfix = None;
+        let _ = state.draw_to_stderr(None);
+    }
+
+    /// Advance the progress bar by n steps.
+    ///
+    /// Like any other update, `update(0)` redraws the bar only if the minimum
+    /// refresh interval (100ms by default) has passed since the last redraw, while never
+    /// advancing it. Use [`refresh`](Self::refresh) to redraw unconditionally.
+    pub fn update(&self, n: u64) {
+        self.update_and_get(n);
+    }
+
+    /// Redraw the progress bar immediately, without advancing it.
+    pub fn refresh(&self) {
+        self.progress.sync();
+        let state = self.state.lock().unwrap();
+        let _ = state.draw_to_stderr(None);
+    }
+
+    /// Advance the progress bar by n steps, and return the progress
+    /// right after this advancement.
+    ///
+    /// Progress made by other threads in the meantime is not counted,
+    /// so the returned values are distinct among concurrent callers.
+    ///
+    /// # Examples
+    /// ```
+    /// # use avance::AvanceBar;
+    /// let pb = AvanceBar::new(1000);
+    /// for _ in 0..100 {
+    ///     if pb.update_and_get(10) % 200 == 0 {
+    ///         // do something every 200 steps
+    ///     }
+    /// }
+    /// ```
+    pub fn update_and_get(&self, n: u64) -> u64 {
+        self.advance(n, |state| {
+            let _ = state.draw_to_stderr(None);
+        })
+    }
+
+    /// Advance the progress bar by one step, with the same effect as
+    /// [`update(1)`](Self::update). If you don't want to invoke inc
+    /// manually, see another method at [`with_iter`](Self::with_iter).
+    ///
+    /// # Examples
+    /// ```
+    /// # use avance::AvanceBar;
+    /// let pb = AvanceBar::new(1000);
+    /// for _ in 0..1000 {
+    ///     // ...
+    ///     pb.inc();
+    /// }
+    /// ```
+    pub fn inc(&self) {
+        self.update(1);
+    }
+
+    /// Advance the progress bar by one step, and return the progress
+    /// right after this advancement.
+    ///
+    /// See [`update_and_get`](Self::update_and_get)
+    pub fn inc_and_get(&self) -> u64 {
+        self.update_and_get(1)
+    }
+
+    /// Advance the progress bar by `delta` steps, or move it back if `delta` is
+    /// negative, such as when a task undoes some work. The progress never goes below 0.
+    ///
+    /// The rate only counts the progress made, so it never becomes negative.
+    ///
+    /// # Examples
+    /// ```
+    /// # use avance::AvanceBar;
+    /// let pb = AvanceBar::new(100);
+    /// pb.update_signed(60);
+    /// // Backtrack
+    /// pb.update_signed(-20);
+    /// # #[cfg(not(feature = "disabled"))]
+    /// assert!(pb.render_line(80).contains(" 40/100 "));
+    /// ```
+    pub fn update_signed(&self, delta: i64) {
+        if delta >= 0 {
+            return self.update(delta as u64);
+        }
+        if DISABLED {
+            return;
+        }
+
+        let back = delta.unsigned_abs();
+        let _ = self
+            .progress
+            .n
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                Some(n.saturating_sub(back))
+            });
+        let now = self.progress.now();
+        self.progress.changed.store(now, Ordering::Relaxed);
+        // The progress may be far behind where the clock is read next
+        self.progress.next_check.store(0, Ordering::Relaxed);
+        self.update(0);
+    }
+
+    /// Advance the progress bar by one step without drawing it, which is left
+    /// to [`pump`](Self::pump).
+    ///
+    /// It only does an atomic addition, which never locks, allocates or blocks,
+    /// so it's async-signal-safe on platforms with lock-free 64-bit atomics, and can
+    /// be called from signal handlers or real-time threads.
+    ///
+    /// # Examples
+    /// ```
+    /// # use avance::AvanceBar;
+    /// let pb = AvanceBar::new(100);
+    /// std::thread::scope(|t| {
+    ///     // e.g. in a signal handler
+    ///     t.spawn(|| (0..100).for_each(|_| pb.inc_deferred()));
+    /// });
+    /// // in the main loop
+    /// pb.pump();
+    /// ```
+    pub fn inc_deferred(&self) {
+        if !DISABLED {
+            self.progress.inc(1);
+        }
+    }
+
+    /// Redraw the progress bar with the progress made by
+    /// [`inc_deferred`](Self::inc_deferred), which is meant to be called
+    /// in a loop. Like updates, it only redraws once per refresh interval.
+    pub fn pump(&self) {
+        let progress = self.progress.n.load(Ordering::Acquire);
+        if progress != self.progress.last.load(Ordering::Acquire) {
+            // Deferred increments don't read the clock
+            let now = self.progress.now();
+            self.progress.changed.store(now, Ordering::Relaxed);
+        }
+        self.update(0);
+    }
+
+    /// Advance the progress bar to the number of milliseconds elapsed since it
+    /// started (but not beyond its total), and return whether the total is reached.
+    ///
+    /// Meant for bars whose work is just time passing, see [`timer`](Self::timer).
+    /// An unbounded bar is advanced without limit and never completes.
+    pub fn tick_by_time(&self) -> bool {
+        let elapsed = self.progress.now() / 1_000_000;
+        let total = self.state.lock().unwrap().total;
+        let target = total.map_or(elapsed, |total| min(total, elapsed));
+
+        let n = self.progress.n.load(Ordering::Relaxed);
+        if target > n {
+            self.update(target - n);
+        }
+        total.map_or(false, |total| target >= total)
+    }
+
+    /// Set a secondary progress ahead of the progress, such as the data buffered
+    /// ahead of the data processed in streaming. The part of the bar between them
+    /// is filled with a lighter character (`-`, or `░` for non-ASCII styles).
+    ///
+    /// A secondary progress behind the progress isn't shown. Like updates,
+    /// it only redraws once per refresh interval.
+    ///
+    /// # Examples
+    /// ```
+    /// # use avance::{AvanceBar, Style};
+    /// let pb = AvanceBar::new(100).with_style(Style::ASCII);
+    /// pb.update(30);
+    /// pb.set_secondary(60);
+    /// // e.g. " 30%|#########9---------              | 30/100 [00:00<00:00]"
+    /// ```
+    pub fn set_secondary(&self, n: u64) {
+        self.state.lock().unwrap().secondary = n;
+        self.update(0);
+    }
+
+    /// Set the progress to a fraction of the total, which is clamped to `[0, 1]`.
+    /// The progress may go backwards. Like updates, it only redraws once per
+    /// refresh interval.
+    ///
+    /// Does nothing for an unbounded bar. See [`new_percentage`](Self::new_percentage)
+    pub fn set_fraction(&self, fraction: f64) {
+        if DISABLED {
+            return;
+        }
+        let total = match self.state.lock().unwrap().total {
+            Some(total) => total,
+            None => return,
+        };
+        let fraction = if fraction.is_nan() {
+            0.0
+        } else {
+            fraction.clamp(0.0, 1.0)
+        };
+        let target = min((fraction * total as f64).round() as u64, total);
+
+        if self.progress.n.swap(target, Ordering::AcqRel) != target {
+            let now = self.progress.now();
+            self.progress.changed.store(now, Ordering::Relaxed);
+        }
+        self.update(0);
+    }
+
+    /// Time since the progress bar was last advanced (or created),
+    /// which is useful for detecting stalled tasks.
+    ///
+    /// # Examples
+    /// ```
+    /// # use avance::AvanceBar;
+    /// # use std::time::Duration;
+    /// let pb = AvanceBar::new(100);
+    /// if pb.idle_time() > Duration::from_secs(30) {
+    ///     avance::println("stalled for 30s");
+    /// }
+    /// ```
+    pub fn idle_time(&self) -> Duration {
+        Duration::from_nanos(self.progress.idle_time())
+    }
+
+    /// Time since the progress bar started, which is when it was created
+    /// unless set by [`set_start_time`](Self::set_start_time).
+    pub fn elapsed(&self) -> Duration {
+        Duration::from_nanos(self.progress.now())
+    }
+
+    /// Whether the progress bar is being drawn on the terminal, which is false when
+    /// stderr isn't a terminal, the bar is closed, or it's hidden beyond the rows
+    /// allowed for bars.
+    ///
+    /// Useful for skipping expensive work that only affects the display.
+    ///
+    /// # Examples
+    /// ```
+    /// # use avance::AvanceBar;
+    /// let pb = AvanceBar::new(100);
+    /// for i in pb.with_iter(0..100) {
+    ///     if pb.is_visible() {
+    ///         pb.set_postfix(format!("{:?}", vec![i; 10]));
+    ///     }
+    /// }
+    /// ```
+    pub fn is_visible(&self) -> bool {
+        self.state.lock().unwrap().visible()
+    }
+
+    /// Manually stop the progress bar, and leave the current progress on terminal.
+    /// Usually users don't have to call this method directly, as a progress bar will
+    /// be closed automatically when dropped.
+    ///
+    /// Users should close a bar manually when they want to preserve the rendering order
+    /// of progress bars, otherwise, progress bars will be closed in the order of being
+    /// dropped (Closing order is the same as the rendering order).
+    pub fn close(&self) {
+        let mut state = self.state.lock().unwrap();
+        let warning = state.finish();
+        let aftermath = state.aftermath(warning);
+        drop(state);
+
+        aftermath.run();
+    }
+
+    /// Close the progress bar, leaving a message in place of its final state,
+    /// such as a summary of the work done. Other bars are moved as usual.
+    ///
+    /// The message takes as many rows as the bar, so it should be a single line
+    /// (or two for a [multiline](Self::set_multiline) bar), and is truncated to
+    /// the terminal width. Does nothing if the bar is already closed.
+    ///
+    /// # Examples
+    /// ```
+    /// # use avance::AvanceBar;
+    /// let pb = AvanceBar::new(4).with_desc("download");
+    /// pb.update(4);
+    /// pb.close_with(format_args!("Downloaded {} files", 4));
+    /// ```
+    pub fn close_with(&self, message: impl Display) {
+        // Formatting may run user code, which is done without locking
+        let message = message.to_string();
+        let mut state = self.state.lock().unwrap();
+        if state.try_get_pos().is_some() {
+            state.message = Some(message);
+        }
+        let warning = state.finish();
+        let aftermath = state.aftermath(warning);
+        drop(state);
+
+        aftermath.run();
+    }
+
+    /// An identifier of the progress bar, which is unique among all bars
+    /// and shared by its clones. See [`observer`](crate::observer)
+    pub fn id(&self) -> u64 {
+        self.state.lock().unwrap().id
+    }
+
+    /// Create a child progress bar, which advances this bar by one step when it's closed
+    /// (or dropped), such as a bar of the chunks in a file under a bar of files.
+    ///
+    /// A bar can only be linked to a newly created child, so there are no cycles.
+    ///
+    /// # Examples
+    /// ```
+    /// # use avance::AvanceBar;
+    /// let files = AvanceBar::new(3).with_desc("files");
+    /// for _ in 0..3 {
+    ///     let chunks = files.child(100).with_desc("chunks");
+    ///     for _ in chunks.with_iter(0..100) {
+    ///         // ...
+    ///     }
+    /// }
+    /// ```
+    pub fn child(&self, total: u64) -> AvanceBar {
+        let child = AvanceBar::new(total);
+        child.state.lock().unwrap().parent = Some(self.clone());
+        child
+    }
+
+    /// Set the style (default: [`Style::ASCII`]) of a progress bar.
+    pub fn set_style(&self, style: Style) {
+        let mut state = self.state.lock().unwrap();
+        if state.restyle(style) {
+            let _ = state.draw_to_stderr(None);
+        }
+    }
+
+    /// Set the user-custom style of a progress bar.
+    pub fn set_style_str(&self, s: impl Into<Cow<'static, str>>) {
+        self.set_style(Style::Custom(s.into()));
+    }
+
+    /// Set the user-custom style of a progress bar, rejecting styles
+    /// that can't be rendered properly.
+    ///
+    /// See [`Style::try_from_str`] for the validation rules.
+    ///
+    /// # Examples
+    /// ```
+    /// # use avance::{AvanceBar, style::StyleError};
+    /// let pb = AvanceBar::new(1000);
+    /// assert!(pb.try_set_style_str("=>-").is_ok());
+    /// assert_eq!(pb.try_set_style_str("=>"), Err(StyleError::TooShort));
+    /// ```
+    pub fn try_set_style_str(
+        &self,
+        s: impl Into<Cow<'static, str>>,
+    ) -> std::result::Result<(), StyleError> {
+        let style = Style::try_from_str(s)?;
+        self.set_style(style);
+        Ok(())
+    }
+
+    /// Set the total length of a progress bar, or None if it's unknown.
+    ///
+    /// Useful when the total is only known after the progress has started,
+    /// such as a download that receives its content length late.
+    ///
+    /// # Examples
+    /// ```
+    /// # use avance::AvanceBar;
+    /// let pb = AvanceBar::new(0);
+    /// pb.set_total(None);
+    /// pb.update(10);
+    /// // size is known now
+    /// pb.set_total(Some(100));
+    /// ```
+    pub fn set_total(&self, total: Option<u64>) {
+        let mut state = self.state.lock().unwrap();
+        if state.total == total {
+            return;
+        }
+        state.set_total(total);
+        // Bounded and unbounded bars have different layouts
+        let _ = state.clear();
+        let _ = state.draw_to_stderr(None);
+    }
+
+    /// Set a progress bar's width
+    pub fn set_width(&self, width: u16) {
+        let mut state = self.state.lock().unwrap();
+        if state.resize(width) {
+            let _ = state.clear();
+            let _ = state.draw_to_stderr(None);
+        }
+    }
+
+    /// Set a description (prefix) evaluated each time the progress bar is rendered,
+    /// which is useful when it depends on live states, such as the file being processed.
+    ///
+    /// Unlike calling [`set_desc`](Self::set_desc) on every item, it costs nothing
+    /// between two redraws. It's replaced by a later [`set_desc`](Self::set_desc).
+    ///
+    /// The closure runs while the bar is locked, so it must not call any method
+    /// of the same bar, otherwise it deadlocks, and a panic in it poisons the bar.
+    ///
+    /// # Examples
+    /// ```
+    /// # use avance::AvanceBar;
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// let current = Arc::new(Mutex::new(String::new()));
+    /// let pb = AvanceBar::new(3);
+    /// let file = Arc::clone(&current);
+    /// pb.set_dynamic_desc(move || file.lock().unwrap().clone().into());
+    ///
+    /// for name in ["a.txt", "b.txt", "c.txt"] {
+    ///     *current.lock().unwrap() = name.to_string();
+    ///     pb.inc();
+    /// }
+    /// ```
+    pub fn set_dynamic_desc<F>(&self, desc: F)
+    where
+        F: Fn() -> Cow<'static, str> + Send + Sync + 'static,
+    {
+        let mut state = self.state.lock().unwrap();
+        state.template.dynamic_desc = Some(DynamicDesc(Arc::new(desc)));
+        let _ = state.draw_to_stderr(None);
+    }
+
+    /// Set the description (prefix) of a progress bar.
+    pub fn set_desc(&self, desc: impl Into<Cow<'static, str>>) {
+        let desc = desc.into();
+        let mut state = self.state.lock().unwrap();
+        state.template.desc = Some(desc);
+        state.template.dynamic_desc = None;
+        let _ = state.draw_to_stderr(None);
+    }
+
+    /// If unit_scale (default: false) is set true, prints the number of iterations
+    /// with an appropriate SI metric prefix.
+    pub fn set_unit_scale(&self, unit_scale: bool) {
+        self.state.lock().unwrap().template.unit_scale = unit_scale;
+    }
+
+    /// If countdown (default: false) is set true, displays the remaining steps
+    /// and percentage instead of the finished ones. The bar still fills up as
+    /// the progress advances.
+    pub fn set_countdown(&self, countdown: bool) {
+        let mut state = self.state.lock().unwrap();
+        state.template.countdown = countdown;
+        let _ = state.draw_to_stderr(None);
+    }
+
+    /// If eta_absolute (default: false) is set true, displays the estimated time of day
+    /// when the bar will be done, like `@06:15`, instead of the remaining time.
+    /// Unbounded bars show `?` as their ETA.
+    ///
+    /// The time of day is in UTC unless [`set_utc_offset`] is called, since
+    /// the local time zone isn't looked up.
+    pub fn set_eta_absolute(&self, eta_absolute: bool) {
+        let mut state = self.state.lock().unwrap();
+        state.template.eta_absolute = eta_absolute;
+        let _ = state.draw_to_stderr(None);
+    }
+
+    /// If raw_rate (default: false) is set true, displays the instant rate since the
+    /// last refresh, without blending it with the average rate. The displayed rate
+    /// is more accurate but noisier.
+    pub fn set_raw_rate(&self, raw_rate: bool) {
+        self.state.lock().unwrap().template.raw_rate = raw_rate;
+    }
+
+    /// If refresh_on_inc (default: false) is set true, redraws the bar on every
+    /// update instead of at most once per 100ms.
+    ///
+    /// Every update then locks the bar and writes to the terminal, which is
+    /// much slower than the default, so only use it for slow progress or demos.
+    pub fn set_refresh_on_inc(&self, refresh_on_inc: bool) {
+        self.progress.eager.store(refresh_on_inc, Ordering::Relaxed);
+    }
+
+    /// Set how many times per second (default: 10) the bar may be redrawn on updates,
+    /// at most 240.
+    ///
+    /// A rate of 0 (or NaN) disables redraws on updates, so the bar is only
+    /// redrawn on closing, configuration changes and [`refresh`](Self::refresh).
+    pub fn set_refresh_rate(&self, hz: f64) {
+        let interval = match hz > 0.0 {
+            true => (1e9 / hz.min(MAX_REFRESH_RATE)) as u64,
+            false => u64::MAX,
+        };
+        self.progress.interval.store(interval, Ordering::Relaxed);
+    }
+
+    /// If adaptive_refresh (default: false) is set true, the refresh interval of a
+    /// bounded bar is halved once it reaches 95% of the total, so that the last few
+    /// percent move smoothly without redrawing more often before.
+    pub fn set_adaptive_refresh(&self, adaptive_refresh: bool) {
+        self.progress
+            .adaptive
+            .store(adaptive_refresh, Ordering::Relaxed);
+    }
+
+    /// Hide the bar once the given time (default: None) has passed since it reached
+    /// its total, moving up the bars below to reclaim its rows. Useful for
+    /// transient bars in long-running programs.
+    ///
+    /// The bar is closed without leaving its final line. It's kept if its progress
+    /// falls below the total in the meantime, or if it's closed before. Unbounded
+    /// bars are never hidden. A thread is spawned to wait for the time.
+    pub fn set_hide_after_complete(&self, after: Option<Duration>) {
+        let after = after.map_or(u64::MAX, |after| {
+            min(after.as_nanos(), u64::MAX as u128 - 1) as u64
+        });
+        self.progress.hide_after.store(after, Ordering::Relaxed);
+    }
+
+    /// If auto_close (default: false) is set true, closes the bar as soon as an update
+    /// brings it to its total, leaving its final line, rather than when it's dropped.
+    /// Unbounded bars are never closed this way.
+    ///
+    /// The bar is closed for all of its clones, even if other threads are still
+    /// advancing it. Their updates are counted, but no longer drawn.
+    pub fn set_auto_close(&self, auto_close: bool) {
+        self.progress
+            .auto_close
+            .store(auto_close, Ordering::Relaxed);
+    }
+
+    /// Minimum interval between two redraws of the bar on updates
+    pub(crate) fn interval(&self) -> Duration {
+        Duration::from_nanos(self.progress.interval.load(Ordering::Relaxed))
+    }
+
+    /// If verify_total (default: false) is set true, warns on stderr when the
+    /// increments of a bounded bar won't add up to its total.
+    ///
+    /// It's meant for catching off-by-chunk bugs when a bar is shared by
+    /// several [`with_iter`](Self::with_iter) calls: a warning is printed as soon
+    /// as the lengths of the wrapped iterators exceed the total, and when the bar
+    /// is closed with its progress not matching the total.
+    pub fn set_verify_total(&self, verify_total: bool) {
+        self.state.lock().unwrap().verify_total = verify_total;
+    }
+
+    /// Set the number of decimal places (default: 0) in the percentage of a bounded bar.
+    ///
+    /// The percentage is rounded down, so a bar never shows 100% before completing.
+    pub fn set_percent_precision(&self, precision: u8) {
+        let mut state = self.state.lock().unwrap();
+        state.template.percent_precision = precision;
+        let _ = state.draw_to_stderr(None);
+    }
+
+    /// Set where the percentage sits relative to the description
+    /// (default: [`PercentPosition::AfterDesc`]).
+    pub fn set_percent_position(&self, position: PercentPosition) {
+        let mut state = self.state.lock().unwrap();
+        state.template.percent_position = position;
+        let _ = state.draw_to_stderr(None);
+    }
+
+    /// If miniters_auto (default: false) is set true, the clock is only read once
+    /// every N updates, where N is estimated from the observed speed to be the
+    /// number of iterations within a refresh interval.
+    ///
+    /// It saves the cost of reading the clock on every update in very hot loops.
+    /// In turn, the bar may redraw late if the progress suddenly slows down, and
+    /// [`idle_time`](Self::idle_time) is only refreshed when the clock is read.
+    pub fn set_miniters_auto(&self, miniters_auto: bool) {
+        self.progress.auto.store(miniters_auto, Ordering::Relaxed);
+        self.progress.next_check.store(0, Ordering::Relaxed);
+    }
+
+    /// Count the elapsed time (and the rate) of a progress bar from `start` instead of
+    /// its creation, so that several bars for the phases of one job can share a clock.
+    ///
+    /// A start time in the future is treated as now.
+    pub fn set_start_time(&self, start: Instant) {
+        self.progress.set_start(start);
+        self.refresh();
+    }
+
+    /// Save the progress and the elapsed time to a file, and resume from the
+    /// progress saved there by a previous run, so that a long job which may be
+    /// killed shows its cumulative progress and a meaningful rate.
+    ///
+    /// The file is written at most once per second while the bar is redrawn,
+    /// and when it's closed. It's removed once the bar is closed having reached
+    /// its total, so the next run starts fresh. A missing or corrupt file is ignored.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # use avance::AvanceBar;
+    /// let pb = AvanceBar::new(1_000_000).with_persistence("job.progress");
+    /// // Skip the items done by previous runs
+    /// let done = pb.update_and_get(0);
+    /// for _ in done..1_000_000 {
+    ///     // ...
+    ///     pb.inc();
+    /// }
+    /// ```
+    pub fn with_persistence(self, path: impl Into<PathBuf>) -> Self {
+        if DISABLED {
+            return self;
+        }
+        let path = path.into();
+        if let Some((n, elapsed)) = load_progress(&path) {
+            self.progress.n.store(n, Ordering::Release);
+            if let Some(start) = self.progress.instant().checked_sub(elapsed) {
+                self.progress.set_start(start);
+            }
+            // The restored progress isn't made since the last redraw
+            self.progress.update();
+        }
+        self.state.lock().unwrap().persistence = Some(path);
+        self.refresh();
+        self
+    }
+
+    /// Read the time from another clock, such as a [`MockClock`](crate::MockClock)
+    /// in tests, which makes the elapsed time, the rate and the ETA deterministic.
+    /// The bar starts over at the current time of the clock.
+    ///
+    /// Only the first clock given to a bar is used.
+    ///
+    /// # Examples
+    /// ```
+    /// # use avance::{AvanceBar, MockClock};
+    /// # use std::sync::Arc;
+    /// # use std::time::Duration;
+    /// let clock = Arc::new(MockClock::new());
+    /// let pb = AvanceBar::new(100).with_clock(clock.clone());
+    /// clock.advance(Duration::from_secs(10));
+    /// pb.update(50);
+    /// # #[cfg(not(feature = "disabled"))]
+    /// assert!(pb.render_line(80).contains("50/100 [00:10<00:10, 5.00it/s]"));
+    /// ```
+    pub fn with_clock(self, clock: Arc<dyn Clock>) -> Self {
+        let now = clock.now();
+        if self.progress.clock.set(ClockSource(clock)).is_ok() {
+            self.progress.set_start(now);
+            self.progress.update();
+            self.refresh();
+        }
+        self
+    }
+
+    /// Show a sparkline (like `▁▂▃▅▇`) of the latest `width` instant rates after the
+    /// rate, which tells whether the progress is speeding up or slowing down.
+    ///
+    /// A rate is recorded each time the bar is redrawn. Set `width` to 0 (default)
+    /// to hide the sparkline.
+    pub fn set_sparkline(&self, width: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.template.sparkline = width;
+        while state.rates.len() > width {
+            state.rates.pop_front();
+        }
+        let _ = state.draw_to_stderr(None);
+    }
+
+    /// If taskbar_progress (default: false) is set true, the progress is also reported
+    /// with the `OSC 9;4` escape sequence each time the bar is redrawn, which terminals
+    /// like Windows Terminal and ConEmu show in the taskbar or the tab title.
+    ///
+    /// An unbounded bar is reported as indeterminate, and the report is cleared when
+    /// the bar is closed. Nothing is emitted when stderr isn't a terminal.
+    pub fn set_taskbar_progress(&self, taskbar_progress: bool) {
+        let mut state = self.state.lock().unwrap();
+        state.template.taskbar_progress = taskbar_progress;
+        let _ = state.draw_to_stderr(None);
+    }
+
+    /// If multiline (default: false) is set true, the bar takes two rows: the
+    /// description and the bar on the first one, and the statistics (like
+    /// `50/100 [00:01<00:01, 50.00it/s]`) with the postfix on the second one.
+    ///
+    /// It leaves much more room for the bar on narrow terminals. A two-line bar
+    /// counts as two bars for [`set_max_progress_bars`], and only its first line
+    /// is drawn if the cursor isn't managed (see [`set_manage_cursor`]).
+    pub fn set_multiline(&self, multiline: bool) {
+        let mut state = self.state.lock().unwrap();
+        if state.template.multiline == multiline {
+            return;
+        }
+        let before = used_rows();
+        state.template.multiline = multiline;
+        reshape(state.id, state.height());
+        drop(state);
+
+        relayout(before);
+    }
+
+    /// If compact (default: false) is set true, the bar only shows its description,
+    /// percentage, bar and counts (like `task:  50%|#####     | 50/100`), leaving out
+    /// the time, the rate and the postfix. Meant for rows of a
+    /// [compact group](crate::BarGroup::compact), which shows column labels once.
+    ///
+    /// A compact bar always takes one row, so it's mutually exclusive with
+    /// [`multiline`](Self::set_multiline), which is ignored.
+    pub fn set_compact(&self, compact: bool) {
+        let mut state = self.state.lock().unwrap();
+        if state.template.compact == compact {
+            return;
+        }
+        let before = used_rows();
+        state.template.compact = compact;
+        reshape(state.id, state.height());
+        drop(state);
+
+        relayout(before);
+    }
+
+    /// If verbose (default: false) is set true, the bar takes three rows, showing
+    /// the statistics below the bar as in the [multiline](Self::set_multiline)
+    /// layout, and then the exact counts, the instant and average rates, and the
+    /// remaining time along with the time of completion, such as
+    /// `30/100 | 5.00it/s now, 4.00it/s on average | ETA 00:14 (@14:32)`.
+    ///
+    /// Meant to be toggled by an interactive application, such as on a key press,
+    /// to expand a bar. A verbose bar is never [compact](Self::set_compact).
+    pub fn set_verbose(&self, verbose: bool) {
+        let mut state = self.state.lock().unwrap();
+        if state.template.verbose == verbose {
+            return;
+        }
+        let before = used_rows();
+        state.template.verbose = verbose;
+        reshape(state.id, state.height());
+        drop(state);
+
+        relayout(before);
+    }
+
+    /// If show_rate (default: true) is set false, the rate (like `12.30it/s`) and
+    /// the [sparkline](Self::set_sparkline) are left out of the statistics, which is
+    /// useful when the rate is meaningless, such as for [percentage bars](Self::new_percentage).
+    pub fn set_show_rate(&self, show_rate: bool) {
+        let mut state = self.state.lock().unwrap();
+        state.template.show_rate = show_rate;
+        let _ = state.draw_to_stderr(None);
+    }
+
+    /// Set a rough estimate of the total (default: None) for an unbounded bar,
+    /// which is then drawn like a bounded bar. The shown percentage is
+    /// `1 - exp(-n / estimate)`, so it slows down when approaching 100%, which
+    /// is only shown once the estimate is exceeded dozens of times over.
+    ///
+    /// The estimate is only shown (like `120/~100`), but no ETA is given.
+    /// Bounded bars are not affected.
+    pub fn set_estimate(&self, estimate: Option<u64>) {
+        let mut state = self.state.lock().unwrap();
+        if state.template.estimate == estimate {
+            return;
+        }
+        state.template.estimate = estimate;
+        // The layout changes
+        let _ = state.clear();
+        let _ = state.draw_to_stderr(None);
+    }
+
+    /// Set how a bounded bar is shown once its progress goes beyond the total
+    /// (default: [`TotalOverflow::Exceed`]), which happens when the total is
+    /// under-reported, such as by the size hint of a custom iterator.
+    ///
+    /// Only the display is affected, the total itself is kept.
+    pub fn set_total_overflow(&self, overflow: TotalOverflow) {
+        let mut state = self.state.lock().unwrap();
+        if state.template.total_overflow == overflow {
+            return;
+        }
+        state.template.total_overflow = overflow;
+        // The layout may change
+        let _ = state.clear();
+        let _ = state.draw_to_stderr(None);
+    }
+
+    /// If smart_eta_color (default: false) is set true, the ETA of a bounded bar is
+    /// green when it drops faster than the time passes, which means the progress is
+    /// speeding up, and red when it drops slower or even rises, which means the
+    /// progress is slowing down or stalling.
+    ///
+    /// The trend is followed on each redraw. Plain lines printed when stderr isn't
+    /// a terminal are never colored.
+    pub fn set_smart_eta_color(&self, smart_eta_color: bool) {
+        let mut state = self.state.lock().unwrap();
+        state.template.smart_eta_color = smart_eta_color;
+        let _ = state.draw_to_stderr(None);
+    }
+
+    /// Set the color theme (default: [`Theme::Default`]) of a progress bar.
+    pub fn set_theme(&self, theme: Theme) {
+        let mut state = self.state.lock().unwrap();
+        state.template.theme = theme;
+        let _ = state.draw_to_stderr(None);
+    }
+
+    /// Set the number of columns (default: the terminal width) of the plain lines
+    /// printed when stderr isn't a terminal, e.g. redirected to a file.
+    ///
+    /// Otherwise, the layout of those lines depends on whichever terminal the
+    /// program happens to run in. Drawing on a terminal is not affected.
+    pub fn set_ncols(&self, ncols: Option<u16>) {
+        self.state.lock().unwrap().template.ncols = ncols;
+    }
+
+    /// If indeterminate (default: false) is set true, an unbounded progress bar
+    /// shows a block bouncing left and right across the bar area besides the count.
+    ///
+    /// The block is drawn with the filled character of the bar's style, and moves
+    /// one step each time the bar is redrawn. Bounded bars are not affected.
+    pub fn set_indeterminate(&self, indeterminate: bool) {
+        let mut state = self.state.lock().unwrap();
+        state.template.indeterminate = indeterminate;
+        let _ = state.draw_to_stderr(None);
+    }
+
+    /// Set the icon shown before the bar when it's closed after reaching the total.
+    /// Unbounded bars are always considered successful.
+    pub fn set_success_icon(&self, icon: char) {
+        self.state.lock().unwrap().template.success_icon = Some(icon);
+    }
+
+    /// Set the icon shown before the bar when it's closed before reaching the total.
+    pub fn set_fail_icon(&self, icon: char) {
+        self.state.lock().unwrap().template.fail_icon = Some(icon);
+    }
+
+    /// Set the minimum width (default: 10) of the bar itself. When the terminal is
+    /// too narrow, the description and then the postfix get truncated to keep it.
+    pub fn set_min_bar_width(&self, min_bar_width: u16) {
+        let mut state = self.state.lock().unwrap();
+        state.template.min_bar_width = min_bar_width;
+        let _ = state.draw_to_stderr(None);
+    }
+
+    /// Render the progress bar as a plain string within `width` columns,
+    /// without any terminal control sequences.
+    ///
+    /// Useful when the drawing is owned by another library, such as a TUI framework.
+    ///
+    /// # Examples
+    /// ```
+    /// # use avance::AvanceBar;
+    /// let pb = AvanceBar::new(100).with_desc("task");
+    /// pb.update(50);
+    /// # #[cfg(not(feature = "disabled"))]
+    /// assert!(pb.render_line(80).starts_with("task:  50%|"));
+    /// ```
+    pub fn render_line(&self, width: u16) -> String {
+        self.state.lock().unwrap().render(width, false)
+    }
+}
+
+// Private Interface
+impl AvanceBar {
+    /// Creates the row of column labels above compact bars
+    pub(crate) fn compact_header() -> Self {
+        let pb = Self::from_total(None);
+        pb.state.lock().unwrap().template.header = true;
+        pb.refresh();
+        pb
+    }
+
+    /// Creates a progress bar from an iterator's size hint
+    pub(crate) fn with_hint(size_hint: Option<usize>) -> Self {
+        Self::from_total(size_hint.map(|s| s as u64))
+    }
+
+    /// Creates a progress bar and registers it for [`render_all`]
+    pub(crate) fn from_total(total: Option<u64>) -> Self {
+        let progress = Arc::new(AtomicProgress::new());
+        let state = Arc::new(Mutex::new(State::new(total, Arc::clone(&progress))));
+        let id = state.lock().unwrap().id;
+        if !DISABLED {
+            registry()
+                .lock()
+                .unwrap()
+                .insert(id, Arc::downgrade(&state));
+        }
+
+        if is_sticky() && is_terminal() {
+            let _ = reserve_rows(&mut stderr().lock());
+        }
+        if RESERVE_LINE.load(Ordering::Relaxed) && is_terminal() {
+            let _ = reserve_line(&mut stderr().lock());
+        }
+        if !DISABLED && observer::is_observed() {
+            observer::notify(Event::Created(id));
+        }
+
+        AvanceBar { state, progress }
+    }
+
+    /// Current position of the progress bar, or None if it's closed.
+    pub(crate) fn position(&self) -> Option<u16> {
+        self.state.lock().unwrap().try_get_pos()
+    }
+
+    /// Total length of the progress bar, or None if it's unbounded.
+    pub(crate) fn total(&self) -> Option<u64> {
+        self.state.lock().unwrap().total
+    }
+
+    /// The progress of the bar at the moment
+    pub(crate) fn snapshot(&self) -> Snapshot {
+        self.state.lock().unwrap().snapshot()
+    }
+
+    /// Hide the bar once the time set by [`set_hide_after_complete`](Self::set_hide_after_complete)
+    /// has passed, if it's still complete and open by then
+    fn schedule_hide(&self) {
+        let after = self.progress.hide_after.load(Ordering::Relaxed);
+        if after == u64::MAX || self.progress.hide_scheduled.swap(true, Ordering::AcqRel) {
+            return;
+        }
+
+        // Hiding the bar shouldn't keep it alive
+        let state = Arc::downgrade(&self.state);
+        let progress = Arc::clone(&self.progress);
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_nanos(after));
+            let state = match state.upgrade() {
+                Some(state) => state,
+                None => return,
+            };
+            let pb = AvanceBar { state, progress };
+            let n = pb.progress.n.load(Ordering::Acquire);
+            if n >= pb.progress.complete_at.load(Ordering::Relaxed) {
+                discard(&[&pb]);
+            } else {
+                // Not complete anymore, so wait for the next completion
+                pb.progress.hide_scheduled.store(false, Ordering::Release);
+            }
+        });
+    }
+
+    /// Advance the progress bar by n steps, and `draw` it if it's time to refresh
+    fn advance(&self, n: u64, draw: impl FnOnce(&mut State)) -> u64 {
+        if DISABLED {
+            return 0;
+        }
+
+        let progress = match n {
+            0 => {
+                self.progress.sync();
+                self.progress.n.load(Ordering::Acquire)
+            }
+            n => self.progress.inc(n),
+        };
+        if progress >= self.progress.complete_at.load(Ordering::Relaxed) {
+            if self.progress.auto_close.load(Ordering::Relaxed) {
+                self.close();
+                return progress;
+            }
+            self.schedule_hide();
+        }
+        if n != 0 && self.progress.skip(progress) {
+            return progress;
+        }
+
+        let now = self.progress.now();
+        if n != 0 {
+            self.progress.changed.store(now, Ordering::Relaxed);
+        }
+        self.progress.schedule(progress, now);
+
+        if !self.progress.ready(progress, now) {
+            return progress;
+        }
+        if DROP_BUSY.load(Ordering::Relaxed) && self.progress.drawing.swap(true, Ordering::AcqRel) {
+            // Another thread is drawing, probably to a slow stderr
+            return progress;
+        }
+
+        let _drawing = Drawing(&self.progress.drawing);
+        self.redraw_with(progress, draw);
+        progress
+    }
+
+    /// Redraw the bar at `progress` regardless of the refresh interval,
+    /// such as when a wrapped iterator is exhausted
+    pub(crate) fn redraw(&self) {
+        if DISABLED {
+            return;
+        }
+        self.progress.sync();
+        let progress = self.progress.n.load(Ordering::Acquire);
+        self.redraw_with(progress, |state| {
+            let _ = state.draw_to_stderr(None);
+        });
+    }
+
+    fn redraw_with(&self, progress: u64, draw: impl FnOnce(&mut State)) {
+        let mut state = self.state.lock().unwrap();
+        if state.template.smart_eta_color {
+            state.record_eta();
+        }
+        if state.template.sparkline > 0 {
+            let gap = progress.saturating_sub(self.progress.last.load(Ordering::Acquire));
+            let since_last = self.progress.since_last() as f64 / 1e9;
+            state.record_rate(gap as f64 / since_last);
+        }
+        draw(&mut state);
+        state.persist(false);
+        let event = observer::is_observed().then(|| Event::Redrawn(state.id, state.snapshot()));
+        drop(state);
+        self.progress.update();
+
+        if let Some(event) = event {
+            observer::notify(event);
+        }
+    }
+}
+
+/// Advance a progress bar by the number of bytes written to it.
+///
+/// Bytes are only counted, not sunk anywhere. Flushing forces a redraw.
+///
+/// # Examples
+/// ```
+/// # use avance::AvanceBar;
+/// let data = vec![0u8; 4096];
+/// let pb = AvanceBar::new(data.len() as u64).with_unit_scale(true);
+/// std::io::copy(&mut data.as_slice(), &mut &pb).unwrap();
+/// ```
+impl Write for &AvanceBar {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.update(buf.len() as u64);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.refresh();
+        Ok(())
+    }
+}
+
+/// Integers which can be used as the total length of a progress bar
+pub trait Total {
+    /// Convert to u64, saturating at the bounds
+    fn into_total(self) -> u64;
+}
+
+macro_rules! impl_total_unsigned {
+    ($($t:ty),*) => {$(
+        impl Total for $t {
+            fn into_total(self) -> u64 {
+                u64::try_from(self).unwrap_or(u64::MAX)
+            }
+        }
+    )*};
+}
+
+macro_rules! impl_total_signed {
+    ($($t:ty),*) => {$(
+        impl Total for $t {
+            fn into_total(self) -> u64 {
+                u64::try_from(self).unwrap_or(if self < 0 { 0 } else { u64::MAX })
+            }
+        }
+    )*};
+}
+
+impl_total_unsigned!(u8, u16, u32, u64, u128, usize);
+impl_total_signed!(i8, i16, i32, i64, i128, isize);
+
+/// Options of a progress bar, see [`AvanceBar::from_config`]
+///
+/// Options left as `None` keep their defaults, such as the style set by
+/// [`set_default_style`].
+#[derive(Debug, Clone, Default)]
+pub struct BarOptions {
+    /// The total length, or None for an unbounded bar
+    pub total: Option<u64>,
+    /// See [`AvanceBar::set_style`]
+    pub style: Option<Style>,
+    /// See [`AvanceBar::set_width`]
+    pub width: Option<u16>,
+    /// See [`AvanceBar::set_desc`]
+    pub desc: Option<Cow<'static, str>>,
+    /// See [`AvanceBar::set_postfix`]
+    pub postfix: Option<Cow<'static, str>>,
+    /// See [`AvanceBar::set_unit_scale`]
+    pub unit_scale: bool,
+}
+
+/// How a bounded bar is shown once its progress goes beyond the total,
+/// such as when an iterator yields more items than its size hint says
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TotalOverflow {
+    /// Show the progress beyond the total as it is, like `150/100` at 100%
+    #[default]
+    Exceed,
+
+    /// Raise the total to the progress, like `150/150`
+    Promote,
+
+    /// Keep the total, and cap the shown progress at it, like `100/100`
+    Clamp,
+
+    /// Show it as an unbounded bar, like `150it`
+    Unbounded,
+}
+
+#[derive(Debug)]
+struct State {
+    id: ID,
+    progress: Arc<AtomicProgress>,
+    template: Template,
+    total: Option<u64>,
+    // Progress (in steps of the dumb mode) which has been printed
+    logged: Cell<u64>,
+    closed: bool,
+    verify_total: bool,
+    // Sum of the lengths of iterators wrapped by this bar
+    planned: u64,
+    // Number of redraws, which drives the indeterminate animation
+    frame: Cell<u64>,
+    // Latest instant rates shown in the sparkline
+    rates: VecDeque<f64>,
+    // The bar advanced when this bar is closed
+    parent: Option<AvanceBar>,
+    // Whether the closing is followed up, see `aftermath`
+    reported: bool,
+    // The latest elapsed time and ETA (in seconds), and the smoothed rate of the ETA change
+    eta: Option<(f64, f64)>,
+    eta_trend: f64,
+    // Shown in place of the bar once it's closed, see `AvanceBar::close_with`
+    message: Option<String>,
+    // Where the progress is saved, and when (in nanoseconds since the beginning)
+    persistence: Option<PathBuf>,
+    persisted: u64,
+    // A progress ahead of `n`, see `AvanceBar::set_secondary`
+    secondary: u64,
+    // Where the output goes instead of stderr, if created in a capture
+    sink: Option<testing::Sink>,
+}
+
+impl State {
+    fn new(total: Option<u64>, progress: Arc<AtomicProgress>) -> Self {
+        let mut state = Self {
+            id: next_free_pos(),
+            progress,
+            template: Template::new(),
+            total: None,
+            logged: Cell::new(0),
+            closed: false,
+            verify_total: false,
+            planned: 0,
+            frame: Cell::new(0),
+            rates: VecDeque::new(),
+            parent: None,
+            reported: false,
+            eta: None,
+            eta_trend: -1.0,
+            message: None,
+            persistence: None,
+            persisted: 0,
+            secondary: 0,
+            sink: testing::sink(),
+        };
+        state.set_total(total);
+        state
+    }
+
+    fn set_total(&mut self, total: Option<u64>) {
+        self.total = total;
+        // 95% of the total, see `AvanceBar::set_adaptive_refresh`
+        let boost_from = total.map_or(u64::MAX, |total| total - total / 20);
+        self.progress
+            .boost_from
+            .store(boost_from, Ordering::Relaxed);
+        let complete_at = total.unwrap_or(u64::MAX);
+        self.progress
+            .complete_at
+            .store(complete_at, Ordering::Relaxed);
+    }
+
+    fn draw<W: Write>(&self, pos: Option<u16>, target: &mut W) -> Result<()> {
+        if pos.is_none() && !self.drawable() {
+            return Ok(());
+        }
+        let pos = if let Some(pos) = pos {
             pos
         } else {
-            self.get_pos()
+            self.get_pos()
+        };
+
+        let ncols = terminal_size().0;
+        let (nrows, indicator) = layout();
+        if pos >= nrows {
+            return Ok(());
+        }
+
+        self.frame.set(self.frame.get().wrapping_add(1));
+        let rendered = self.render(ncols, true);
+        if let Some(seq) = self.taskbar_seq() {
+            target.queue(Print(seq))?;
+        }
+
+        // Each line of the bar takes a row, unless it's covered by the indicator
+        let top = terminal_size()
+            .1
+            .saturating_sub(RESERVED.load(Ordering::Relaxed));
+        // Rows are drawn from top to bottom in one pass, so the cursor only
+        // goes back up once, and not at all for a bar on the top row
+        let mut below = 0;
+        for (row, line) in (pos..nrows).zip(rendered.split('\n')) {
+            let msg = match row == nrows - 1 && indicator {
+                true => "... (more hidden) ...",
+                false => line,
+            };
+            let padding = (ncols as usize).saturating_sub(format::visible_len(msg));
+            let msg = format!("{}{}", msg, " ".repeat(padding));
+
+            if !manages_cursor() {
+                // Only the top row can be drawn without moving the cursor
+                if row == 0 {
+                    target.queue(Print('\r'))?.queue(Print(msg))?;
+                }
+                break;
+            }
+
+            if is_sticky() {
+                // Bars are drawn at the reserved rows below the scrolling region
+                target
+                    .queue(SavePosition)?
+                    .queue(MoveTo(0, top + row))?
+                    .queue(Print(msg))?
+                    .queue(RestorePosition)?;
+            } else if row != 0 {
+                target
+                    .queue(Print("\n".repeat((row - below) as usize)))?
+                    .queue(Print(msg))?;
+                below = row;
+            } else {
+                target.queue(Print('\r'))?.queue(Print(msg))?;
+            }
+        }
+        if below != 0 {
+            target.queue(MoveUp(below))?.queue(MoveToColumn(ncols))?;
+        }
+        target.flush()
+    }
+
+    fn draw_to_stderr(&self, pos: Option<u16>) -> Result<()> {
+        if !self.on_terminal() {
+            let step = DUMB_STEP.load(Ordering::Relaxed);
+            return self.log(step, false, &mut self.output());
+        }
+        self.draw(pos, &mut stderr().lock())
+    }
+
+    /// Whether the bar is drawn to a terminal, rather than printed as plain lines
+    fn on_terminal(&self) -> bool {
+        self.sink.is_none() && is_terminal()
+    }
+
+    /// Where the plain lines of the bar are printed
+    fn output(&self) -> Output {
+        Output::new(self.sink.clone().or_else(testing::sink))
+    }
+
+    /// Print the progress as a plain line, each time it advances by another `step`
+    /// percent or when the bar is closing. Used when the cursor can't be moved.
+    fn log<W: Write>(&self, step: u8, closing: bool, target: &mut W) -> Result<()> {
+        if step == 0 || self.try_get_pos().is_none() {
+            return Ok(());
+        }
+
+        let n = self.progress.n.load(Ordering::Relaxed);
+        let progressed = match self.total {
+            Some(total) if total > 0 && n < total => {
+                (n as u128 * 100 / total as u128) as u64 / step as u64
+            }
+            _ => 0,
+        };
+
+        if closing || progressed > self.logged.get() {
+            self.logged.set(progressed);
+            let ncols = self.template.ncols.unwrap_or_else(|| terminal_size().0);
+            writeln!(target, "{}", self.render(ncols, false))?;
+            target.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Number of rows taken by the bar
+    fn height(&self) -> u16 {
+        match (
+            self.template.verbose,
+            self.template.multiline && !self.compact(),
+        ) {
+            (true, _) => 3,
+            (false, true) => 2,
+            (false, false) => 1,
+        }
+    }
+
+    /// Whether the bar is shown in a compact row, which a verbose bar never is
+    fn compact(&self) -> bool {
+        self.template.compact && !self.template.verbose
+    }
+
+    fn drawable(&self) -> bool {
+        self.on_terminal() && self.try_get_pos().is_some()
+    }
+
+    /// Whether the bar itself is shown, rather than hidden or replaced by the indicator
+    fn visible(&self) -> bool {
+        if !self.drawable() {
+            return false;
+        }
+        let (nrows, indicator) = layout();
+        let shown = if indicator {
+            nrows.saturating_sub(1)
+        } else {
+            nrows
+        };
+        self.get_pos() < shown
+    }
+
+    fn close(&mut self) -> Result<()> {
+        if self.try_get_pos().is_none() {
+            // already closed
+            return Ok(());
+        }
+        self.closed = true;
+
+        if !self.on_terminal() {
+            let step = DUMB_STEP.load(Ordering::Relaxed);
+            let _ = self.log(step, true, &mut self.output());
+        }
+
+        // Close the current bar and move up other bars
+        reposition(self.id);
+        registry().lock().unwrap().remove(&self.id);
+
+        if !self.on_terminal() {
+            return Ok(());
+        }
+
+        let mut target = stderr().lock();
+
+        // force update (only displaying average its)
+        self.progress.update();
+
+        if is_sticky() {
+            // Leave the final state in the scrolling region, like other outputs
+            let msg = self.render(terminal_size().0, true);
+            if let Some(seq) = self.taskbar_seq() {
+                target.queue(Print(seq))?;
+            }
+            target
+                .queue(MoveToColumn(0))?
+                .queue(Print(msg))?
+                .queue(Print('\n'))?;
+            reserve_rows(&mut target)?;
+            return target.flush();
+        }
+
+        let _ = self.draw(Some(0), &mut target);
+
+        // Move cursor to the end of the next line
+        let ncols = terminal_size().0;
+        let height = match manages_cursor() {
+            true => self.height(),
+            false => 1,
+        };
+
+        target.queue(Print("\n".repeat(height as usize)))?;
+        if !is_finished() && manages_cursor() {
+            // only do this when some bars are still in progress
+            target.queue(MoveToColumn(ncols))?;
+        }
+        target.flush()
+    }
+
+    /// What's left to do after the bar is closed, only given once
+    fn aftermath(&mut self, warning: Option<String>) -> Aftermath {
+        if !self.closed || self.reported {
+            return Aftermath::default();
+        }
+        self.reported = true;
+
+        Aftermath {
+            warning,
+            parent: self.parent.take(),
+            event: observer::is_observed().then(|| Event::Closed(self.id, self.snapshot())),
+        }
+    }
+
+    /// The progress and the total to show, see [`TotalOverflow`]
+    fn shown_progress(&self) -> (u64, Option<u64>) {
+        let n = self.progress.n.load(Ordering::Relaxed);
+        match self.total {
+            Some(total) if n > total => match self.template.total_overflow {
+                TotalOverflow::Exceed => (n, Some(total)),
+                TotalOverflow::Promote => (n, Some(n)),
+                TotalOverflow::Clamp => (total, Some(total)),
+                TotalOverflow::Unbounded => (n, None),
+            },
+            total => (n, total),
+        }
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            n: self.progress.n.load(Ordering::Relaxed),
+            total: self.total,
+            elapsed: Duration::from_nanos(self.progress.now()),
+            rate: self.rate(),
+            desc: self.desc().map(String::from),
+        }
+    }
+
+    /// Close the bar silently, without leaving its final line
+    fn discard(&mut self) {
+        if self.try_get_pos().is_none() {
+            return;
+        }
+        self.closed = true;
+        reposition(self.id);
+        registry().lock().unwrap().remove(&self.id);
+
+        if let Some(seq) = self.taskbar_seq().filter(|_| self.on_terminal()) {
+            let _ = stderr().lock().queue(Print(seq)).and_then(|t| t.flush());
+        }
+    }
+
+    /// The `OSC 9;4` sequence reporting the progress to the terminal, if enabled
+    fn taskbar_seq(&self) -> Option<String> {
+        if !self.template.taskbar_progress {
+            return None;
+        }
+        if self.closed {
+            return Some(String::from("\x1b]9;4;0\x07"));
+        }
+        let n = self.progress.n.load(Ordering::Relaxed);
+        Some(match self.total {
+            Some(0) => String::from("\x1b]9;4;1;100\x07"),
+            Some(total) => {
+                let percent = min(n, total) as u128 * 100 / total as u128;
+                format!("\x1b]9;4;1;{}\x07", percent)
+            }
+            None => String::from("\x1b]9;4;3\x07"),
+        })
+    }
+
+    /// Close the bar, and return a warning if its progress doesn't match
+    /// the total when verify_total is set
+    fn finish(&mut self) -> Option<String> {
+        // Nothing to check if it's already closed
+        self.try_get_pos()?;
+        self.persist_on_close();
+        let _ = self.close();
+        self.total_mismatch()
+    }
+
+    /// Save the progress and the elapsed time, at most once per second unless `forced`
+    fn persist(&mut self, forced: bool) {
+        let now = self.progress.now();
+        let path = match &self.persistence {
+            Some(path) if forced || now.saturating_sub(self.persisted) >= 1_000_000_000 => path,
+            _ => return,
+        };
+        self.persisted = now;
+        let n = self.progress.n.load(Ordering::Relaxed);
+        let _ = std::fs::write(path, format!("{} {}\n", n, now));
+    }
+
+    /// Save the progress of an unfinished bar for the next run, or forget it
+    fn persist_on_close(&mut self) {
+        let n = self.progress.n.load(Ordering::Relaxed);
+        match (&self.persistence, self.total) {
+            (Some(path), Some(total)) if n >= total => {
+                let _ = std::fs::remove_file(path);
+            }
+            _ => self.persist(true),
+        }
+    }
+
+    /// Account for the length of a newly wrapped iterator, and return a warning
+    /// if the total will be exceeded
+    fn plan(&mut self, len: u64) -> Option<String> {
+        self.planned = self.planned.saturating_add(len);
+        match self.total {
+            Some(total) if self.verify_total && self.planned > total => Some(format!(
+                "avance: {}wrapped iterators have {} items in total, beyond the total of {}",
+                self.warning_prefix(),
+                self.planned,
+                total
+            )),
+            _ => None,
+        }
+    }
+
+    fn total_mismatch(&self) -> Option<String> {
+        let n = self.progress.n.load(Ordering::Relaxed);
+        match self.total {
+            Some(total) if self.verify_total && n != total => Some(format!(
+                "avance: {}closed at {}/{}, which doesn't match the total",
+                self.warning_prefix(),
+                n,
+                total
+            )),
+            _ => None,
+        }
+    }
+
+    fn warning_prefix(&self) -> String {
+        self.desc()
+            .map_or_else(String::new, |desc| format!("[{}] ", desc))
+    }
+
+    /// The description, evaluated now if it's dynamic
+    fn desc(&self) -> Option<Cow<'static, str>> {
+        match &self.template.dynamic_desc {
+            Some(DynamicDesc(f)) => Some(f()),
+            None => self.template.desc.clone(),
+        }
+    }
+
+    /// Keep the latest rates for the sparkline
+    fn record_rate(&mut self, rate: f64) {
+        if !rate.is_finite() {
+            return;
+        }
+        self.rates.push_back(rate);
+        while self.rates.len() > self.template.sparkline {
+            self.rates.pop_front();
+        }
+    }
+
+    /// Follow how fast the ETA changes, which is -1 second per second
+    /// if the progress goes at a constant speed
+    fn record_eta(&mut self) {
+        let n = self.progress.n.load(Ordering::Relaxed);
+        let total = match self.total {
+            Some(total) if n > 0 && n < total => total,
+            _ => return,
+        };
+
+        let elapsed = self.progress.now() as f64 / 1e9;
+        let pct = n as f64 / total as f64;
+        let eta = elapsed / pct * (1. - pct);
+        if let Some((prev_elapsed, prev_eta)) = self.eta {
+            if elapsed > prev_elapsed {
+                let change = (eta - prev_eta) / (elapsed - prev_elapsed);
+                self.eta_trend = self.eta_trend * 0.7 + change * 0.3;
+            }
+        }
+        self.eta = Some((elapsed, eta));
+    }
+
+    /// Set the width, and return whether it's changed, which requires
+    /// the bar to be cleared and redrawn
+    fn resize(&mut self, width: u16) -> bool {
+        let changed = self.template.width != Some(width);
+        self.template.width = Some(width);
+        changed
+    }
+
+    /// Set the style, and return whether it's changed, which requires
+    /// the bar to be redrawn
+    fn restyle(&mut self, style: Style) -> bool {
+        let changed = self.template.style.as_ref() != style.as_ref();
+        self.template.style = style;
+        changed
+    }
+
+    /// Sweep a progress bar from the terminal.
+    /// Useful when a progress bar's width was changed.
+    fn clear(&self) -> Result<()> {
+        if !self.drawable() {
+            return Ok(());
+        }
+
+        let mut target = stderr().lock();
+        let pos = self.get_pos();
+        let rows = pos..min(pos.saturating_add(self.height()), nrows());
+        sweep_rows(rows, &mut target)
+    }
+
+    fn try_get_pos(&self) -> Option<Pos> {
+        let positions = positions().lock().unwrap();
+        positions.get(&self.id).map(|&(pos, _)| pos)
+    }
+
+    fn get_pos(&self) -> Pos {
+        self.try_get_pos().unwrap()
+    }
+}
+
+impl Display for State {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> std::fmt::Result {
+        let terminal_width = terminal_size().0;
+        fmt.write_str(&self.render(terminal_width, false))
+    }
+}
+
+impl State {
+    /// Render the progress bar as a line fitting in the given number of columns.
+    /// Colors of the theme are applied only if `themed` is set.
+    fn render(&self, ncols: u16, themed: bool) -> String {
+        let message = self.message.as_ref().filter(|_| self.closed);
+        let rendered = match (message, self.final_icon()) {
+            (Some(message), _) => {
+                // Exactly as many lines as the bar, so that no stale line is left
+                let height = self.height() as usize;
+                let lines = message.split('\n').chain(std::iter::repeat(""));
+                lines.take(height).collect::<Vec<_>>().join("\n")
+            }
+            (None, Some(icon)) => {
+                let ncols = ncols.saturating_sub(format::char_width(icon) as u16 + 1);
+                format!("{} {}", icon, self.render_bar(ncols, themed))
+            }
+            (None, None) => self.render_bar(ncols, themed),
+        };
+        let rendered = match self.template.verbose && message.is_none() {
+            true => format!("{}\n{}", rendered, self.details()),
+            false => rendered,
+        };
+
+        // A line wider than the terminal would wrap, and misplace the bars below
+        let ncols = ncols as usize;
+        if rendered
+            .split('\n')
+            .all(|line| format::visible_len(line) <= ncols)
+        {
+            return rendered;
+        }
+        rendered
+            .split('\n')
+            .map(|line| format::truncate_visible(line, ncols))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// The extra row of a verbose bar, with the exact counts, both rates and both ETAs
+    fn details(&self) -> String {
+        let elapsed = self.progress.now() as f64 / 1e9;
+        let (n, total) = self.shown_progress();
+        let gap = n.saturating_sub(self.progress.last.load(Ordering::Relaxed));
+        let since_last = self.progress.since_last() as f64 / 1e9;
+        let its = |rate: f64| match rate.is_finite() {
+            true => format!("{:.02}it/s", rate),
+            false => String::from("?it/s"),
+        };
+        let instant = its(rate(n, elapsed, gap, since_last, true));
+        let average = its(rate(n, elapsed, 0, since_last, false));
+
+        let (total, eta) = match total {
+            Some(total) if n > 0 && total > 0 && elapsed > 0.0 => {
+                let pct = (n as f64 / total as f64).min(1.0);
+                let eta = elapsed / pct * (1. - pct);
+                let eta = format!(
+                    "{} ({})",
+                    format::format_time(eta as u64),
+                    eta_at(SystemTime::now(), eta)
+                );
+                (total.to_string(), eta)
+            }
+            Some(total) => (total.to_string(), String::from("?")),
+            None => (String::from("?"), String::from("?")),
+        };
+        format!(
+            "{}/{} | {} now, {} on average | ETA {}",
+            n, total, instant, average, eta
+        )
+    }
+
+    /// The icon shown before a closed bar, depending on whether it's complete
+    fn final_icon(&self) -> Option<char> {
+        if !self.closed || self.template.header {
+            return None;
+        }
+
+        let n = self.progress.n.load(Ordering::Relaxed);
+        match self.total {
+            Some(total) if n < total => self.template.fail_icon,
+            _ => self.template.success_icon,
+        }
+    }
+
+    /// Iterations per second, see [`rate`]
+    fn rate(&self) -> f64 {
+        let elapsed = self.progress.now() as f64 / 1e9;
+        let n = self.progress.n.load(Ordering::Relaxed);
+        let last_n = self.progress.last.load(Ordering::Relaxed);
+        let since_last = self.progress.since_last() as f64 / 1e9;
+
+        // The progress may be behind the last refresh if it was reset in the meantime
+        let gap = n.saturating_sub(last_n);
+        rate(n, elapsed, gap, since_last, self.template.raw_rate)
+    }
+
+    fn render_bar(&self, ncols: u16, themed: bool) -> String {
+        use format::*;
+
+        let elapsed = self.progress.now() as f64 / 1e9;
+        let desc = self
+            .desc()
+            .map_or_else(String::new, |desc| format!("{}: ", desc));
+        let postfix = self
+            .template
+            .postfix
+            .as_ref()
+            .filter(|p| !p.is_empty() && !self.compact())
+            .map_or_else(String::new, |p| format!(", {}", p));
+
+        let width = self.template.width.map_or(ncols, |w| min(w, ncols));
+        let palette = self.template.theme.palette().filter(|_| themed);
+
+        let (n, total) = self.shown_progress();
+        let its = self.rate();
+        let (base, suffix) = match self.template.binary_units {
+            true => (1024, "B"),
+            false => (1000, ""),
+        };
+
+        let time = format_time(elapsed as u64);
+        let eta_absolute = self.template.eta_absolute;
+        // No rate yet if no time has elapsed
+        let its = match its.is_finite() {
+            true => format!("{:.02}it/s", its),
+            false => String::from("?it/s"),
+        };
+        let its = match (self.template.show_rate, self.template.sparkline) {
+            (false, _) => String::new(),
+            (true, 0) => format!(", {}", its),
+            (true, width) => format!(", {} {}", its, sparkline(&self.rates, width)),
+        };
+        if self.template.header {
+            return compact_header(width);
+        }
+        // Compact bars only show the description, the bar and the counts
+        let compact = self.compact();
+        // The statistics move to the second line in the multiline layout
+        let multiline = (self.template.multiline || self.template.verbose) && !compact;
+        let sep = if multiline { "\n" } else { " " };
+
+        if total.is_none() && self.template.estimate.is_none() {
+            // No idea when an unbounded bar will be done
+            let time = match eta_absolute {
+                true => format!("{}<?", time),
+                false => time,
+            };
+            let desc = match palette {
+                Some(p) => desc.with(p.desc).to_string(),
+                None => desc,
+            };
+            let n = match self.template.unit_scale {
+                true => format_sizeof_with(n, base, suffix),
+                false => n.to_string(),
+            };
+            if !self.template.indeterminate {
+                return match compact {
+                    true => format!("{}{}it", desc, n),
+                    false => format!("{}{}it{}[{}{}]{}", desc, n, sep, time, its, postfix),
+                };
+            }
+
+            let l_bar = format!("{}{}it |", desc, n);
+            let r_bar = match compact {
+                true => String::from("|"),
+                false => format!("|{}[{}{}]{}", sep, time, its, postfix),
+            };
+            let r_len = r_bar.lines().next().map_or(0, |l| l.chars().count());
+            let limit = (width as usize).saturating_sub(format::visible_len(&l_bar) + r_len);
+            let (before, block, after) = sweep(limit, self.frame.get());
+
+            let style: Vec<_> = self.template.style.as_ref().chars().collect();
+            let (filled, background) = (style[0], style[style.len() - 1]);
+            let before = background.to_string().repeat(before);
+            let block = filled.to_string().repeat(block);
+            let after = background.to_string().repeat(after);
+
+            return match palette {
+                Some(p) => format!(
+                    "{}{}{}{}{}",
+                    l_bar,
+                    before.with(p.background),
+                    block.with(p.filled),
+                    after.with(p.background),
+                    r_bar
+                ),
+                None => format!("{}{}{}{}{}", l_bar, before, block, after, r_bar),
+            };
+        }
+
+        let (pct, shown_pct, stats, shown_stats) = match total {
+            Some(total) => {
+                let pct = match total {
+                    0 => 1.0,
+                    total => (n as f64 / total as f64).clamp(0.0, 1.0),
+                };
+                let eta = match n {
+                    // No ETA yet if nothing is done, or no time has elapsed
+                    0 => String::from("?"),
+                    _ if elapsed <= 0.0 => String::from("?"),
+                    _ if eta_absolute => eta_at(SystemTime::now(), elapsed / pct * (1. - pct)),
+                    _ => format_time((elapsed / pct * (1. - pct)) as u64),
+                };
+
+                // Counting down only changes the numbers being displayed
+                let (shown_n, shown_pct) = match self.template.countdown {
+                    true => (total.saturating_sub(n), 1.0 - pct),
+                    false => (n, pct),
+                };
+                let (n_str, total_str) = match self.template.unit_scale {
+                    true => format_sizeof_pair(shown_n, total, base, suffix),
+                    false => (shown_n.to_string(), total.to_string()),
+                };
+                let stats = match compact {
+                    true => format!("{}/{}", n_str, total_str),
+                    false => format!("{}/{} [{}<{}{}", n_str, total_str, time, eta, its),
+                };
+                // The same statistics with a colored ETA, whose length isn't counted
+                let shown_stats = match eta_color(self.eta_trend) {
+                    Some(color) if themed && self.template.smart_eta_color && !compact => format!(
+                        "{}/{} [{}<{}{}",
+                        n_str,
+                        total_str,
+                        time,
+                        eta.with(color),
+                        its
+                    ),
+                    _ => stats.clone(),
+                };
+                (pct, shown_pct, stats, shown_stats)
+            }
+            None => {
+                // An estimated total, which is approached until it's greatly exceeded
+                let estimate = self.template.estimate.unwrap_or(1);
+                let pct = 1.0 - (-(n as f64) / max(estimate, 1) as f64).exp();
+                let (n_str, estimate_str) = match self.template.unit_scale {
+                    true => format_sizeof_pair(n, estimate, base, suffix),
+                    false => (n.to_string(), estimate.to_string()),
+                };
+                let stats = match compact {
+                    true => format!("{}/~{}", n_str, estimate_str),
+                    false => format!("{}/~{} [{}{}", n_str, estimate_str, time, its),
+                };
+                (pct, pct, stats.clone(), stats)
+            }
+        };
+
+        let precision = self.template.percent_precision as usize;
+        let pct_str = format!(
+            "{:>width$.precision$}%",
+            floor_to(100.0 * shown_pct, precision),
+            width = 3 + precision + min(precision, 1),
+            precision = precision
+        );
+        // Parts of the left bar before and after the description
+        let (head, tail) = match self.template.percent_position {
+            PercentPosition::BeforeDesc => (format!("{} ", pct_str), String::from("|")),
+            PercentPosition::AfterDesc => (String::new(), format!("{}|", pct_str)),
+        };
+
+        // Truncate the description first and then the postfix,
+        // so that the bar keeps at least `min_bar_width` columns
+        let (desc, postfix) = {
+            let (desc_len, postfix_len) = (desc.chars().count(), postfix.chars().count());
+            let (width, min_bar_width) = (width as usize, self.template.min_bar_width as usize);
+            let l_bar_len = head.chars().count() + tail.chars().count() + desc_len;
+            // The statistics, the postfix and the closing bracket
+            let r_bar_len = stats.chars().count() + postfix_len + 1;
+
+            let (desc_cut, postfix_cut) = match multiline {
+                true => (
+                    min(
+                        (l_bar_len + 1 + min_bar_width).saturating_sub(width),
+                        desc_len,
+                    ),
+                    min(r_bar_len.saturating_sub(width), postfix_len),
+                ),
+                false => {
+                    let overflow =
+                        (l_bar_len + 2 + r_bar_len + min_bar_width).saturating_sub(width);
+                    let desc_cut = min(overflow, desc_len);
+                    (desc_cut, min(overflow - desc_cut, postfix_len))
+                }
+            };
+
+            (
+                truncate(&desc, desc_len - desc_cut),
+                truncate(&postfix, postfix_len - postfix_cut),
+            )
+        };
+
+        let (r_bar, below) = match (multiline, compact) {
+            (_, true) => (format!("| {}", shown_stats), String::new()),
+            (true, false) => (String::from("|"), format!("\n{}{}]", shown_stats, postfix)),
+            (false, false) => (format!("| {}{}]", shown_stats, postfix), String::new()),
+        };
+        let limit = (width as usize).saturating_sub(
+            head.chars().count()
+                + desc.chars().count()
+                + tail.chars().count()
+                + visible_len(&r_bar),
+        );
+
+        let style: Vec<_> = self.template.style.as_ref().chars().collect();
+
+        let filled = style[0];
+        let (background, in_progress) = style[1..].split_last().unwrap();
+
+        let m = in_progress.len();
+        // Float to integer casts saturate, but rounding errors must not
+        // push the fill beyond the bar area
+        let k = min((limit as f64 * pct * m as f64) as usize, limit * m);
+        let n_filled = k / m;
+        let current = k % m;
+
+        let done = filled.to_string().repeat(n_filled);
+
+        let current = match n_filled < limit {
+            true => in_progress[current].to_string(),
+            false => String::new(),
+        };
+
+        // The part between the progress and the secondary progress, if any
+        let secondary = match total {
+            Some(total) if total > 0 && self.secondary > n => {
+                min(self.secondary, total) as f64 / total as f64
+            }
+            _ => 0.0,
+        };
+        let n_buffered = min((limit as f64 * secondary) as usize, limit)
+            .saturating_sub(n_filled + current.chars().count());
+        let buffered = match style.iter().all(char::is_ascii) {
+            true => "-",
+            false => "░",
+        }
+        .repeat(n_buffered);
+
+        // Unicode width is not considered at the moment
+        let todo = background
+            .to_string()
+            .repeat(limit.saturating_sub(n_filled + 1 + n_buffered));
+
+        match palette {
+            Some(p) => format!(
+                "{}{}{}{}{}{}{}{}{}",
+                head,
+                desc.with(p.desc),
+                tail,
+                done.with(p.filled),
+                current.with(p.current),
+                buffered.with(p.background),
+                todo.with(p.background),
+                r_bar,
+                below
+            ),
+            None => format!(
+                "{}{}{}{}{}{}{}{}{}",
+                head, desc, tail, done, current, buffered, todo, r_bar, below
+            ),
+        }
+    }
+}
+
+/// Column labels above compact bars, spread across `width` columns
+fn compact_header(width: u16) -> String {
+    let (task, progress, counts) = ("task", "progress", "done/total");
+    let space = (width as usize).saturating_sub(task.len() + progress.len() + counts.len());
+    let left = space / 2;
+    format!(
+        "{}{}{}{}{}",
+        task,
+        " ".repeat(max(left, 1)),
+        progress,
+        " ".repeat(max(space - left, 1)),
+        counts
+    )
+}
+
+/// Heights of bars in a sparkline
+const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// Render the latest rates as a sparkline of `width` columns, relative to the peak rate.
+/// The sparkline is left padded with spaces until there are enough rates.
+fn sparkline(rates: &VecDeque<f64>, width: usize) -> String {
+    let peak = rates.iter().cloned().fold(0.0, f64::max);
+    let sparks = rates
+        .iter()
+        .skip(rates.len().saturating_sub(width))
+        .map(|&r| {
+            let level = match peak > 0.0 {
+                true => (r / peak * 7.0).round() as usize,
+                false => 0,
+            };
+            SPARKS[min(level, 7)]
+        });
+    " ".repeat(width.saturating_sub(rates.len()))
+        .chars()
+        .chain(sparks)
+        .collect()
+}
+
+/// The time of day `eta` seconds after `now`, like `@14:32`
+fn eta_at(now: SystemTime, eta: f64) -> String {
+    let eta = match eta.is_finite() {
+        // Far enough to not overflow the time
+        true => Duration::from_secs_f64(eta.clamp(0.0, 1e10)),
+        false => Duration::ZERO,
+    };
+    let offset = UTC_OFFSET.load(Ordering::Relaxed);
+    format!("@{}", format::format_clock(now + eta, offset))
+}
+
+/// Nanoseconds from `from` to `to`, which are negative if `to` is earlier
+fn nanos_between(from: Instant, to: Instant) -> i64 {
+    match to.checked_duration_since(from) {
+        Some(later) => later.as_nanos() as i64,
+        None => -(from.duration_since(to).as_nanos() as i64),
+    }
+}
+
+/// Green if the ETA drops faster than the time passes (speeding up),
+/// or red if it drops slower or even rises (slowing down)
+fn eta_color(trend: f64) -> Option<Color> {
+    match trend {
+        trend if trend < -1.25 => Some(Color::Green),
+        trend if trend > -0.75 => Some(Color::Red),
+        _ => None,
+    }
+}
+
+/// Round `x` down to `precision` decimal places, so that 100% is never shown too early
+fn floor_to(x: f64, precision: usize) -> f64 {
+    let scale = 10f64.powi(precision as i32);
+    (x * scale).floor() / scale
+}
+
+/// Lengths of the space before the sweeping block, the block, and the space after it,
+/// in a bar area of `limit` columns at the given frame
+fn sweep(limit: usize, frame: u64) -> (usize, usize, usize) {
+    let block = min(3, limit);
+    let span = (limit - block) as u64;
+    if span == 0 {
+        return (0, block, 0);
+    }
+
+    // Bounce back when reaching either end
+    let step = frame % (2 * span);
+    let before = min(step, 2 * span - step) as usize;
+    (before, block, limit - block - before)
+}
+
+/// The progress and the elapsed time saved by [`AvanceBar::with_persistence`]
+fn load_progress(path: &Path) -> Option<(u64, Duration)> {
+    let saved = std::fs::read_to_string(path).ok()?;
+    let mut fields = saved
+        .split_whitespace()
+        .map(|field| field.parse::<u64>().ok());
+    match (fields.next(), fields.next(), fields.next()) {
+        (Some(Some(n)), Some(Some(elapsed)), None) => Some((n, Duration::from_nanos(elapsed))),
+        _ => None,
+    }
+}
+
+/// Iterations per second. The instant rate (of the latest `gap` steps) is blended
+/// with the average rate, unless `raw` is set. Falls back to the average rate when
+/// there's no progress since the last refresh.
+///
+/// Returns NaN if no time has elapsed, which is possible on coarse clocks.
+fn rate(n: u64, elapsed: f64, gap: u64, since_last: f64, raw: bool) -> f64 {
+    if elapsed <= 0.0 {
+        return f64::NAN;
+    }
+
+    // smoothing
+    let factor = 0.7;
+    let average = n as f64 / elapsed;
+    let instant = gap as f64 / since_last;
+
+    match (gap, raw) {
+        (0, _) => average,
+        _ if since_last <= 0.0 => average,
+        (_, true) => instant,
+        (_, false) => average * factor + instant * (1.0 - factor),
+    }
+}
+
+impl Drop for State {
+    fn drop(&mut self) {
+        let warning = self.finish();
+        self.aftermath(warning).run();
+    }
+}
+
+/// Work left after a bar is closed, which must be done with the bar unlocked
+#[derive(Default)]
+struct Aftermath {
+    warning: Option<String>,
+    parent: Option<AvanceBar>,
+    event: Option<Event>,
+}
+
+impl Aftermath {
+    /// Print the warning of the closed bar, advance its parent, and notify the observer.
+    /// No state should be locked by the caller.
+    fn run(self) {
+        if let Some(warning) = self.warning {
+            eprint_above(warning);
+        }
+        if let Some(parent) = self.parent {
+            parent.inc();
+        }
+        if let Some(event) = self.event {
+            observer::notify(event);
+        }
+    }
+}
+
+#[derive(Debug)]
+struct AtomicProgress {
+    begin: Instant,
+    // Nanoseconds added to the time since `begin`, when started at another time
+    offset: AtomicI64,
+    prev: AtomicU64,
+    last: AtomicU64,
+    n: AtomicU64,
+    // When (in nanoseconds since the beginning) the progress was advanced
+    changed: AtomicU64,
+    // Redraw on every update, ignoring the refresh interval
+    eager: AtomicBool,
+    // Minimum interval (in nanoseconds) between two redraws on updates
+    interval: AtomicU64,
+    // Halve the interval once the progress reaches `boost_from`, near the total
+    adaptive: AtomicBool,
+    boost_from: AtomicU64,
+    // The total, or u64::MAX if unbounded
+    complete_at: AtomicU64,
+    // Nanoseconds to hide the bar after it's complete, or u64::MAX to never hide it
+    hide_after: AtomicU64,
+    hide_scheduled: AtomicBool,
+    // Close the bar once the progress reaches `complete_at`
+    auto_close: AtomicBool,
+    // Only read the clock once the progress reaches `next_check`
+    auto: AtomicBool,
+    next_check: AtomicU64,
+    // Whether a thread is drawing the bar on update
+    drawing: AtomicBool,
+    // A counter owned by the user, which is read as the progress
+    source: OnceLock<Arc<AtomicU64>>,
+    // The system clock is read unless another one is given
+    clock: OnceLock<ClockSource>,
+}
+
+struct ClockSource(Arc<dyn Clock>);
+
+// Clears the flag of drawing when dropped, even if the drawing panics
+struct Drawing<'a>(&'a AtomicBool);
+
+impl Drop for Drawing<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+impl std::fmt::Debug for ClockSource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ClockSource")
+    }
+}
+
+impl AtomicProgress {
+    fn new() -> Self {
+        Self {
+            begin: Instant::now(),
+            offset: AtomicI64::new(0),
+            prev: AtomicU64::new(0),
+            last: AtomicU64::new(0),
+            n: AtomicU64::new(0),
+            changed: AtomicU64::new(0),
+            eager: AtomicBool::new(false),
+            interval: AtomicU64::new(INTERVAL),
+            adaptive: AtomicBool::new(false),
+            boost_from: AtomicU64::new(u64::MAX),
+            complete_at: AtomicU64::new(u64::MAX),
+            hide_after: AtomicU64::new(u64::MAX),
+            hide_scheduled: AtomicBool::new(false),
+            auto_close: AtomicBool::new(false),
+            auto: AtomicBool::new(false),
+            next_check: AtomicU64::new(0),
+            drawing: AtomicBool::new(false),
+            source: OnceLock::new(),
+            clock: OnceLock::new(),
+        }
+    }
+
+    /// The current time of the clock
+    fn instant(&self) -> Instant {
+        match self.clock.get() {
+            Some(clock) => clock.0.now(),
+            None => Instant::now(),
+        }
+    }
+
+    /// Nanoseconds since the beginning
+    fn now(&self) -> u64 {
+        let now = nanos_between(self.begin, self.instant()) + self.offset.load(Ordering::Relaxed);
+        max(now, 0) as u64
+    }
+
+    /// Move the beginning to `start` (clamped to now), shifting the recorded times
+    fn set_start(&self, start: Instant) {
+        let start = min(start, self.instant());
+        let offset = nanos_between(start, self.begin);
+
+        let delta = offset - self.offset.swap(offset, Ordering::Relaxed);
+        for time in [&self.prev, &self.changed] {
+            let shifted = time.load(Ordering::Relaxed) as i64 + delta;
+            time.store(max(shifted, 0) as u64, Ordering::Relaxed);
+        }
+    }
+
+    /// Read the progress from the tracked counter, if any
+    fn sync(&self) {
+        if let Some(source) = self.source.get() {
+            let n = source.load(Ordering::Acquire);
+            if self.n.swap(n, Ordering::AcqRel) != n {
+                self.changed.store(self.now(), Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn inc(&self, delta: u64) -> u64 {
+        // fetch_add wraps around on overflow, so does the returned value
+        self.n
+            .fetch_add(delta, Ordering::AcqRel)
+            .wrapping_add(delta)
+    }
+
+    /// Whether reading the clock can be skipped at progress `n` in the auto miniters mode
+    fn skip(&self, n: u64) -> bool {
+        self.auto.load(Ordering::Relaxed)
+            && !self.eager.load(Ordering::Relaxed)
+            && n < self.next_check.load(Ordering::Relaxed)
+    }
+
+    /// Estimate how many iterations take a refresh interval from the speed
+    /// since the last redraw, and postpone the next clock read by that many
+    fn schedule(&self, n: u64, now: u64) {
+        if !self.auto.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let gap = n.saturating_sub(self.last.load(Ordering::Acquire)) as u128;
+        let elapsed = now.saturating_sub(self.prev.load(Ordering::Acquire)) as u128;
+        let miniters = match elapsed {
+            0 => 1,
+            elapsed => {
+                let interval = self.interval_at(n) as u128;
+                let miniters = gap.saturating_mul(interval) / elapsed;
+                max(1, min(miniters, u64::MAX as u128) as u64)
+            }
+        };
+        self.next_check
+            .store(n.saturating_add(miniters), Ordering::Relaxed);
+    }
+
+    /// The refresh interval at progress `n`
+    fn interval_at(&self, n: u64) -> u64 {
+        let interval = self.interval.load(Ordering::Relaxed);
+        match self.adaptive.load(Ordering::Relaxed) && n >= self.boost_from.load(Ordering::Relaxed)
+        {
+            true => interval / 2,
+            false => interval,
+        }
+    }
+
+    fn ready(&self, n: u64, now: u64) -> bool {
+        self.eager.load(Ordering::Relaxed)
+            || now.saturating_sub(self.prev.load(Ordering::Acquire)) > self.interval_at(n)
+    }
+
+    fn idle_time(&self) -> u64 {
+        self.now()
+            .saturating_sub(self.changed.load(Ordering::Relaxed))
+    }
+
+    fn update(&self) {
+        self.prev.store(self.now(), Ordering::Release);
+        self.last
+            .store(self.n.load(Ordering::Acquire), Ordering::Release);
+    }
+
+    fn since_last(&self) -> u64 {
+        let prev = self.prev.load(Ordering::Acquire);
+        self.now().saturating_sub(prev)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Template {
+    style: Style,
+    width: Option<u16>,
+    desc: Option<Cow<'static, str>>,
+    unit_scale: bool,
+    // Scaled counts are bytes with binary prefixes, like `1.50MiB`
+    binary_units: bool,
+    postfix: Option<Cow<'static, str>>,
+    min_bar_width: u16,
+    countdown: bool,
+    eta_absolute: bool,
+    verbose: bool,
+    raw_rate: bool,
+    theme: Theme,
+    success_icon: Option<char>,
+    fail_icon: Option<char>,
+    indeterminate: bool,
+    ncols: Option<u16>,
+    percent_precision: u8,
+    sparkline: usize,
+    percent_position: PercentPosition,
+    dynamic_desc: Option<DynamicDesc>,
+    taskbar_progress: bool,
+    multiline: bool,
+    smart_eta_color: bool,
+    estimate: Option<u64>,
+    total_overflow: TotalOverflow,
+    compact: bool,
+    show_rate: bool,
+    // Column labels of compact bars, drawn in place of the bar
+    header: bool,
+}
+
+/// A description evaluated on rendering
+#[derive(Clone)]
+struct DynamicDesc(Arc<dyn Fn() -> Cow<'static, str> + Send + Sync>);
+
+impl std::fmt::Debug for DynamicDesc {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("DynamicDesc")
+    }
+}
+
+impl Template {
+    /// The template of a new progress bar, see [`set_default_style`]
+    /// and [`set_default_width`]
+    fn new() -> Self {
+        default_template().lock().unwrap().clone()
+    }
+
+    fn builtin() -> Self {
+        Self {
+            style: Default::default(),
+            width: None,
+            desc: None,
+            unit_scale: false,
+            binary_units: false,
+            postfix: None,
+            min_bar_width: 10,
+            countdown: false,
+            eta_absolute: false,
+            verbose: false,
+            raw_rate: false,
+            theme: Theme::Default,
+            success_icon: None,
+            fail_icon: None,
+            indeterminate: false,
+            ncols: None,
+            percent_precision: 0,
+            sparkline: 0,
+            percent_position: PercentPosition::AfterDesc,
+            dynamic_desc: None,
+            taskbar_progress: false,
+            multiline: false,
+            smart_eta_color: false,
+            estimate: None,
+            total_overflow: TotalOverflow::Exceed,
+            compact: false,
+            show_rate: true,
+            header: false,
+        }
+    }
+}
+
+type AtomicState = Arc<Mutex<State>>;
+type ID = u64;
+type Pos = u16;
+
+// Whether progress bars are compiled out by the `disabled` feature
+const DISABLED: bool = cfg!(feature = "disabled");
+
+// Minimun update interval (in nanoseconds)
+const INTERVAL: u64 = 100_000_000;
+// Highest refresh rate (in Hz) of a progress bar
+const MAX_REFRESH_RATE: f64 = 240.0;
+
+// Next unused ID
+static NEXTID: AtomicU64 = AtomicU64::new(0);
+// How many bars are allowed to be shown, or NO_LIMIT to use the terminal height.
+static MAX_BARS: AtomicU32 = AtomicU32::new(NO_LIMIT);
+const NO_LIMIT: u32 = u32::MAX;
+// Book-keeping the positions (the first rows) and the heights of all bars.
+static POSITIONS: OnceLock<Mutex<HashMap<ID, (Pos, u16)>>> = OnceLock::new();
+// States of all bars that haven't been closed, used for rendering without a terminal.
+static REGISTRY: OnceLock<Mutex<HashMap<ID, Weak<Mutex<State>>>>> = OnceLock::new();
+// Percentage of progress between two lines printed in the dumb mode, 0 for disabled.
+// Tests print nothing unless they capture the lines, see `print_plain_lines`.
+static DUMB_STEP: AtomicU8 = AtomicU8::new(if cfg!(test) { 0 } else { 10 });
+// Whether the terminal can't move the cursor, as TERM=dumb is set or escape
+// sequences are not supported
+static DUMB_TERM: OnceLock<bool> = OnceLock::new();
+// Seconds from UTC of absolute ETAs
+static UTC_OFFSET: AtomicI32 = AtomicI32::new(0);
+// Whether bars are pinned to the bottom of the terminal
+static STICKY: AtomicBool = AtomicBool::new(false);
+// Rows at the bottom of the terminal reserved for bars in the sticky mode
+static RESERVED: AtomicU16 = AtomicU16::new(0);
+// Whether the last row shows "... (more hidden) ..." when bars overflow
+static SHOW_INDICATOR: AtomicBool = AtomicBool::new(true);
+// Whether updates skip drawing while another thread is drawing the same bar
+static DROP_BUSY: AtomicBool = AtomicBool::new(false);
+// Whether bars may move the cursor, or only redraw the current line with `\r`
+static MANAGE_CURSOR: AtomicBool = AtomicBool::new(true);
+// Whether to start a new line before drawing the first bar, and whether it's done
+// for the bars on screen
+static RESERVE_LINE: AtomicBool = AtomicBool::new(false);
+static LINE_RESERVED: AtomicBool = AtomicBool::new(false);
+// Terminal size used when the real one is unknown, packed as `cols << 16 | rows`
+static FALLBACK_SIZE: AtomicU32 = AtomicU32::new(pack_size(DEFAULT_TERMINAL_SIZE));
+// Configs shared by all newly created bars.
+static DEFAULT_TEMPLATE: OnceLock<Mutex<Template>> = OnceLock::new();
+// Tests changing the settings above take turns, and restore them when done
+#[cfg(all(test, not(feature = "disabled")))]
+static GLOBALS: Mutex<()> = Mutex::new(());
+
+thread_local! {
+    // Where `AvanceBar::set_postfix_fmt` formats before locking the bar
+    static FORMATTED: RefCell<String> = const { RefCell::new(String::new()) };
+}
+
+/// Terminal size (columns, rows) assumed when the real size is unknown,
+/// such as in headless environments. See [`set_default_terminal_size`].
+pub const DEFAULT_TERMINAL_SIZE: (u16, u16) = (80, 24);
+
+/// Set the terminal size assumed when the real size is unknown
+/// (default: [`DEFAULT_TERMINAL_SIZE`]).
+///
+/// It decides the width of plain lines printed when stderr isn't a terminal
+/// (unless set by [`AvanceBar::set_ncols`]). Zeros are ignored.
+///
+/// # Examples
+/// ```
+/// avance::set_default_terminal_size(120, 40);
+/// ```
+pub fn set_default_terminal_size(cols: u16, rows: u16) {
+    let (default_cols, default_rows) = DEFAULT_TERMINAL_SIZE;
+    let cols = if cols == 0 { default_cols } else { cols };
+    let rows = if rows == 0 { default_rows } else { rows };
+    FALLBACK_SIZE.store(pack_size((cols, rows)), Ordering::Relaxed);
+}
+
+const fn pack_size((cols, rows): (u16, u16)) -> u32 {
+    (cols as u32) << 16 | rows as u32
+}
+
+/// Set how many on-going progress bars can be shown on the screen.
+///
+/// With at most `nbars` bars in progress, all of them are shown in exactly `nbars`
+/// rows or less. Beyond that, the first `nbars` bars are shown, followed by an extra
+/// row of `... (more hidden) ...` (see [`set_show_hidden_indicator`]). Setting it
+/// to 0 hides all bars.
+///
+/// If unspecified, bars adjust to the terminal height, whose last row is used
+/// for the indicator. The terminal height is always respected.
+pub fn set_max_progress_bars(nbars: u16) {
+    MAX_BARS.store(nbars as u32, Ordering::Relaxed);
+}
+
+/// How many on-going progress bars can be shown on the screen, or None if
+/// it adjusts to the terminal height.
+///
+/// See [`set_max_progress_bars`]
+pub fn max_progress_bars() -> Option<u16> {
+    match MAX_BARS.load(Ordering::Relaxed) {
+        NO_LIMIT => None,
+        nbars => Some(nbars as u16),
+    }
+}
+
+/// Show an indicator (default: true) at the last row when there are more bars than
+/// the terminal (or [`set_max_progress_bars`]) allows.
+///
+/// If disabled, the last row is used for a bar instead, and overflowing bars are
+/// silently omitted.
+///
+/// # Examples
+/// ```
+/// # use avance::{set_max_progress_bars, set_show_hidden_indicator};
+/// set_max_progress_bars(2);
+/// set_show_hidden_indicator(false);
+/// ```
+pub fn set_show_hidden_indicator(show: bool) {
+    SHOW_INDICATOR.store(show, Ordering::Relaxed);
+}
+
+/// Drop the redraw of an update (default: false) if another thread is drawing the
+/// same bar at that moment, instead of waiting for it.
+///
+/// When stderr is slow (e.g. piped to a slow consumer), this keeps updates from
+/// being blocked by the drawing of other threads, at the cost of skipped frames.
+/// The next refresh shows the latest progress anyway.
+pub fn set_drop_busy_draws(drop: bool) {
+    DROP_BUSY.store(drop, Ordering::Relaxed);
+}
+
+/// Default minimum interval between two redraws of a progress bar,
+/// see [`AvanceBar::set_refresh_rate`]
+pub fn refresh_interval() -> Duration {
+    Duration::from_nanos(INTERVAL)
+}
+
+/// Pin progress bars to the bottom of the terminal, while other outputs
+/// printed by [`println`] scroll above them.
+///
+/// This is done by limiting the scrolling region of the terminal, which is
+/// supported by most terminal emulators, but not legacy Windows consoles.
+/// Outputs printed by other means may still overlap with the bars.
+///
+/// # Examples
+/// ```no_run
+/// # use avance::{set_sticky, AvanceBar};
+/// set_sticky(true);
+/// let pb = AvanceBar::new(100);
+/// for i in pb.with_iter(0..100) {
+///     avance::println(i);
+/// }
+/// ```
+pub fn set_sticky(sticky: bool) {
+    if STICKY.swap(sticky, Ordering::Relaxed) != sticky && is_terminal() {
+        let _ = reserve_rows(&mut stderr().lock());
+    }
+}
+
+fn is_sticky() -> bool {
+    STICKY.load(Ordering::Relaxed) && manages_cursor()
+}
+
+/// Let bars move the cursor (default: true), which is required for drawing more than
+/// one bar. Disable it when another library (e.g. a TUI renderer) manages the cursor.
+///
+/// When disabled, the top bar is redrawn in place by returning to the start of the
+/// line with `\r`, and other bars are not drawn until they move to the top.
+/// The sticky mode (see [`set_sticky`]) is not available either.
+///
+/// # Examples
+/// ```
+/// # use avance::{set_manage_cursor, AvanceBar};
+/// set_manage_cursor(false);
+/// let pb = AvanceBar::new(100);
+/// ```
+pub fn set_manage_cursor(manage: bool) {
+    MANAGE_CURSOR.store(manage, Ordering::Relaxed);
+}
+
+fn manages_cursor() -> bool {
+    MANAGE_CURSOR.load(Ordering::Relaxed)
+}
+
+/// Start a new line (default: false) before drawing the first of the bars on screen,
+/// so that bars never overwrite the last line of prior outputs.
+///
+/// The first bar is drawn at the line where the cursor is, which is fine after a
+/// complete line like `println!("done")`, but overwrites an incomplete one printed
+/// by `print!` or `eprint!`. Enabling this leaves a blank line after complete lines.
+///
+/// # Examples
+/// ```
+/// # use avance::{set_reserve_line, AvanceBar};
+/// set_reserve_line(true);
+/// eprint!("downloading... ");
+/// let pb = AvanceBar::new(100);
+/// ```
+pub fn set_reserve_line(reserve: bool) {
+    RESERVE_LINE.store(reserve, Ordering::Relaxed);
+}
+
+/// Move the cursor to a new line, unless it's done for the bars on screen
+fn reserve_line<W: Write>(target: &mut W) -> Result<()> {
+    if is_sticky() || !manages_cursor() || LINE_RESERVED.swap(true, Ordering::Relaxed) {
+        return Ok(());
+    }
+    target.queue(Print('\n'))?.flush()
+}
+
+/// Adjust the rows reserved for bars in the sticky mode to the number of active bars
+fn reserve_rows<W: Write>(target: &mut W) -> Result<()> {
+    let wanted = match is_sticky() {
+        true => min(used_rows(), nrows()),
+        false => 0,
+    };
+    let reserved = RESERVED.swap(wanted, Ordering::Relaxed);
+    scroll_region(reserved, wanted, terminal_size().1, target)
+}
+
+/// Shrink or grow the scrolling region, leaving `wanted` rows at the bottom
+fn scroll_region<W: Write>(reserved: u16, wanted: u16, rows: u16, target: &mut W) -> Result<()> {
+    if reserved == wanted {
+        return Ok(());
+    }
+
+    if wanted > reserved {
+        // Push the outputs upwards if the cursor is about to be covered by bars
+        let n = wanted - reserved;
+        target
+            .queue(Print("\n".repeat(n as usize)))?
+            .queue(MoveUp(n))?;
+    }
+
+    // Setting the scrolling region moves the cursor to the top left
+    target.queue(SavePosition)?;
+    match wanted {
+        0 => target.queue(Print("\x1b[r"))?,
+        wanted => target.queue(Print(format!("\x1b[1;{}r", rows.saturating_sub(wanted))))?,
+    };
+    // Sweep the rows no longer used by bars
+    for row in rows.saturating_sub(reserved)..rows.saturating_sub(wanted) {
+        target
+            .queue(MoveTo(0, row))?
+            .queue(Clear(ClearType::CurrentLine))?;
+    }
+    target.queue(RestorePosition)?.flush()
+}
+
+/// Set the style of all progress bars created afterwards.
+///
+/// A bar can still override it with [`with_style`](AvanceBar::with_style).
+///
+/// # Examples
+/// ```
+/// # use avance::{set_default_style, AvanceBar, Style};
+/// set_default_style(Style::Block);
+/// let pb = AvanceBar::new(100); // uses Style::Block
+/// ```
+pub fn set_default_style(style: Style) {
+    default_template().lock().unwrap().style = style;
+}
+
+/// Set the width of all progress bars created afterwards.
+///
+/// A bar can still override it with [`with_width`](AvanceBar::with_width).
+pub fn set_default_width(width: u16) {
+    default_template().lock().unwrap().width = Some(width);
+}
+
+/// Set the offset from UTC in seconds (default: 0) of the times of day shown by
+/// [absolute ETAs](AvanceBar::set_eta_absolute), such as `8 * 3600` for UTC+8.
+pub fn set_utc_offset(seconds: i32) {
+    UTC_OFFSET.store(seconds, Ordering::Relaxed);
+}
+
+/// Print progress bars as plain lines when stderr isn't a terminal, or when the
+/// terminal can't move the cursor (`TERM=dumb`, or legacy Windows consoles without
+/// ANSI support), such as in log files.
+///
+/// A line is printed each time a bar advances by another `step` percent, and when
+/// a bar is closed (default: 10). Set `step` to 0 to print nothing in such environments.
+///
+/// Note that this is on by default, so progress shows up in CI logs, or in files
+/// that stderr is redirected to (like `2>file`), unless the step is set to 0.
+///
+/// # Examples
+/// ```
+/// # use avance::{set_dumb_step, AvanceBar};
+/// // Print at 25%, 50%, 75% and on close
+/// set_dumb_step(25);
+/// ```
+pub fn set_dumb_step(step: u8) {
+    DUMB_STEP.store(step, Ordering::Relaxed);
+}
+
+// Whether stderr is a terminal that supports moving the cursor
+fn is_terminal() -> bool {
+    // is_terminal is stable on 1.70.0
+    let dumb = *DUMB_TERM
+        .get_or_init(|| std::env::var("TERM").map_or(false, |t| t == "dumb") || !supports_ansi());
+    !DISABLED && !dumb && testing::sink().is_none() && stderr().is_tty()
+}
+
+// Legacy Windows consoles only handle escape sequences with the virtual terminal
+// processing enabled, which is attempted here. Bars fall back to the dumb mode
+// if it fails.
+#[cfg(windows)]
+fn supports_ansi() -> bool {
+    crossterm::ansi_support::supports_ansi()
+}
+
+#[cfg(not(windows))]
+fn supports_ansi() -> bool {
+    true
+}
+
+/// Let a test change the global settings, until the guard is dropped
+#[cfg(all(test, not(feature = "disabled")))]
+pub(crate) fn lock_globals() -> std::sync::MutexGuard<'static, ()> {
+    GLOBALS
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+/// Let a test print the plain lines of bars every 10%, as outside of tests, so
+/// that they can be captured. Lines are no longer printed once the guard is dropped.
+#[cfg(all(test, not(feature = "disabled")))]
+pub(crate) fn print_plain_lines() -> impl Drop {
+    struct PlainLines(#[allow(dead_code)] std::sync::MutexGuard<'static, ()>);
+    impl Drop for PlainLines {
+        fn drop(&mut self) {
+            set_dumb_step(0);
+        }
+    }
+
+    let globals = lock_globals();
+    set_dumb_step(10);
+    PlainLines(globals)
+}
+
+fn default_template() -> &'static Mutex<Template> {
+    DEFAULT_TEMPLATE.get_or_init(|| Mutex::new(Template::builtin()))
+}
+
+fn positions() -> &'static Mutex<HashMap<ID, (Pos, u16)>> {
+    POSITIONS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn registry() -> &'static Mutex<HashMap<ID, Weak<Mutex<State>>>> {
+    REGISTRY.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Render all active progress bars as plain strings within `width` columns,
+/// ordered by their positions.
+///
+/// No terminal control sequences are emitted, leaving the drawing to the caller.
+/// See [`AvanceBar::render_line`] for rendering a single bar.
+pub fn render_all(width: u16) -> Vec<String> {
+    active_states()
+        .iter()
+        .map(|state| state.lock().unwrap().render(width, false))
+        .collect()
+}
+
+/// Close all remaining progress bars from top to bottom, and restore the terminal.
+///
+/// It's intended to be called once at the end of `main`, so the final states of bars
+/// show up in their rendering order, instead of the order they are dropped in,
+/// which is unpredictable across threads. Bars which are already closed are skipped.
+///
+/// # Examples
+/// ```
+/// # use avance::AvanceBar;
+/// let pb1 = AvanceBar::new(100);
+/// let pb2 = AvanceBar::new(100);
+/// std::thread::spawn(move || pb2.inc());
+/// pb1.inc();
+///
+/// avance::finalize();
+/// ```
+pub fn finalize() {
+    close_in_order(active_states());
+    if is_terminal() {
+        let _ = reserve_rows(&mut stderr().lock());
+    }
+}
+
+/// Close bars without leaving their final lines, and redraw other bars
+/// moving up to fill the vacated rows.
+pub(crate) fn discard(bars: &[&AvanceBar]) {
+    let before = used_rows();
+    for pb in bars {
+        let mut state = pb.state.lock().unwrap();
+        state.discard();
+        let aftermath = state.aftermath(None);
+        drop(state);
+        aftermath.run();
+    }
+    relayout(before);
+}
+
+/// Redraw all bars after the rows taken by them changed from `before`.
+/// No state should be locked by the caller.
+fn relayout(before: u16) {
+    if !is_terminal() {
+        return;
+    }
+    let after = used_rows();
+    let mut target = stderr().lock();
+    if is_sticky() {
+        let _ = reserve_rows(&mut target);
+    } else {
+        // Sweep the rows no longer used by bars
+        let _ = sweep_rows(after..min(before, nrows()), &mut target);
+    }
+    drop(target);
+
+    for state in active_states() {
+        let _ = state.lock().unwrap().draw_to_stderr(None);
+    }
+}
+
+/// Clear the given rows of bars
+fn sweep_rows<W: Write>(rows: Range<u16>, target: &mut W) -> Result<()> {
+    let top = terminal_size()
+        .1
+        .saturating_sub(RESERVED.load(Ordering::Relaxed));
+    for row in rows {
+        if !manages_cursor() {
+            // Only the top row can be cleared without moving the cursor
+            if row == 0 {
+                target
+                    .queue(Print('\r'))?
+                    .queue(Clear(ClearType::CurrentLine))?;
+            }
+            break;
+        }
+
+        if is_sticky() {
+            target
+                .queue(SavePosition)?
+                .queue(MoveTo(0, top + row))?
+                .queue(Clear(ClearType::CurrentLine))?
+                .queue(RestorePosition)?;
+        } else if row != 0 {
+            target
+                .queue(Print("\n".repeat(row as usize)))?
+                .queue(Clear(ClearType::CurrentLine))?
+                .queue(MoveUp(row))?;
+        } else {
+            target.queue(Clear(ClearType::CurrentLine))?;
+        }
+    }
+    target.flush()
+}
+
+/// Close bars in the given order, and return their final lines
+fn close_in_order(states: Vec<AtomicState>) -> Vec<String> {
+    let mut lines = Vec::new();
+    for state in states {
+        let mut state = state.lock().unwrap();
+        if state.try_get_pos().is_none() {
+            // closed by another thread in the meantime
+            continue;
+        }
+        let warning = state.finish();
+        let aftermath = state.aftermath(warning);
+        lines.push(state.render(terminal_size().0, false));
+        drop(state);
+
+        aftermath.run();
+    }
+    lines
+}
+
+/// Print a line to stdout above all progress bars, without breaking them.
+///
+/// Progress bars assume the cursor stays where they left it, so printing
+/// anything to the terminal by other means while bars are in progress
+/// may scatter the bars.
+///
+/// # Examples
+/// ```
+/// # use avance::AvanceBar;
+/// let pb = AvanceBar::new(100);
+/// for i in pb.with_iter(0..100) {
+///     if i % 10 == 0 {
+///         avance::println(format!("checkpoint {}", i));
+///     }
+/// }
+/// ```
+pub fn println(msg: impl Display) {
+    print_line(msg, &mut stdout());
+}
+
+/// Print a line to stderr above all progress bars, like [`println`].
+///
+/// No state should be locked by the caller, as all bars will be redrawn.
+pub(crate) fn eprint_above(msg: impl Display) {
+    print_line(msg, &mut Output::new(testing::sink()));
+}
+
+fn print_line<O: Write>(msg: impl Display, out: &mut O) {
+    // Formatting may run user code, which may lock a bar, and must not be run
+    // with stderr locked, which is locked after bars when drawing them
+    let msg = msg.to_string();
+    if !is_terminal() || is_sticky() {
+        // The cursor never leaves the scrolling region in the sticky mode
+        let _ = writeln!(out, "{}", msg);
+        return;
+    }
+
+    // Wipe the line of the top bar, whose content moves down after printing
+    let _ = print_above(msg, &mut stderr().lock(), out);
+
+    for state in active_states() {
+        let _ = state.lock().unwrap().draw_to_stderr(None);
+    }
+}
+
+fn print_above<E: Write, O: Write>(msg: impl Display, bars: &mut E, out: &mut O) -> Result<()> {
+    match manages_cursor() {
+        true => bars.queue(MoveToColumn(0))?,
+        false => bars.queue(Print('\r'))?,
+    }
+    .queue(Clear(ClearType::CurrentLine))?
+    .flush()?;
+    writeln!(out, "{}", msg)?;
+    out.flush()
+}
+
+/// States of all active bars, ordered by their positions
+fn active_states() -> Vec<AtomicState> {
+    let mut ordered: Vec<_> = positions()
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(&id, &(pos, _))| (pos, id))
+        .collect();
+    ordered.sort_unstable();
+
+    // The registry must be released before locking any state, as a
+    // state is unregistered with its own lock held.
+    let registry = registry().lock().unwrap();
+    ordered
+        .iter()
+        .filter_map(|(_, id)| registry.get(id).and_then(Weak::upgrade))
+        .collect()
+}
+
+/// Number of progress bars that haven't been closed yet.
+///
+/// # Examples
+/// ```
+/// # use avance::{active_bars, AvanceBar};
+/// let pb = AvanceBar::new(100);
+/// # #[cfg(not(feature = "disabled"))]
+/// assert!(active_bars() >= 1);
+/// ```
+pub fn active_bars() -> usize {
+    positions().lock().unwrap().len()
+}
+
+// Whether all progress bars are closed or not
+fn is_finished() -> bool {
+    positions().lock().unwrap().is_empty()
+}
+
+fn terminal_size() -> (u16, u16) {
+    sanitize_size(terminal::size(), fallback_size())
+}
+
+/// See [`set_default_terminal_size`]
+fn fallback_size() -> (u16, u16) {
+    let fallback = FALLBACK_SIZE.load(Ordering::Relaxed);
+    ((fallback >> 16) as u16, fallback as u16)
+}
+
+// Some detached terminals (e.g. in CI) report a zero size,
+// which is treated the same as an unknown size.
+fn sanitize_size(size: Result<(u16, u16)>, fallback: (u16, u16)) -> (u16, u16) {
+    let (fallback_cols, fallback_rows) = fallback;
+    match size {
+        Ok((cols, rows)) => (
+            if cols == 0 { fallback_cols } else { cols },
+            if rows == 0 { fallback_rows } else { rows },
+        ),
+        Err(_) => (fallback_cols, fallback_rows),
+    }
+}
+
+fn nrows() -> u16 {
+    layout().0
+}
+
+/// Rows used by bars, and whether the last one is the "more hidden" indicator
+fn layout() -> (u16, bool) {
+    layout_of(
+        max_progress_bars(),
+        used_rows() as usize,
+        terminal_size().1,
+        SHOW_INDICATOR.load(Ordering::Relaxed),
+    )
+}
+
+fn layout_of(max_bars: Option<u16>, used: usize, rows: u16, indicator: bool) -> (u16, bool) {
+    match max_bars {
+        None => (rows, indicator),
+        // The extra row is only needed when bars overflow
+        Some(n) if indicator && used > n as usize => (min(n.saturating_add(1), rows), true),
+        Some(n) => (min(n, rows), false),
+    }
+}
+
+fn next_free_pos() -> ID {
+    let next_id = NEXTID.fetch_add(1, Ordering::Relaxed);
+    if DISABLED {
+        // A bar without position is never drawn
+        return next_id;
+    }
+
+    let mut positions = positions().lock().unwrap();
+    let next_pos = positions
+        .values()
+        .map(|&(pos, height)| pos.saturating_add(height))
+        .max()
+        .unwrap_or(0);
+    positions.insert(next_id, (next_pos, 1));
+
+    next_id
+}
+
+fn reposition(id: ID) {
+    let mut positions = positions().lock().unwrap();
+
+    let (closed_pos, height) = positions.remove(&id).unwrap();
+    if positions.is_empty() {
+        // The next bar starts on a fresh line, after the final lines of closed bars
+        LINE_RESERVED.store(false, Ordering::Relaxed);
+    }
+
+    // Move upwards all the bars below the closed bar
+    positions.iter_mut().for_each(|(_, (pos, _))| {
+        if *pos > closed_pos {
+            *pos -= height;
+        }
+    });
+}
+
+/// Change the height of a bar, moving the bars below it
+fn reshape(id: ID, height: u16) {
+    let mut positions = positions().lock().unwrap();
+    let (at, old) = match positions.get_mut(&id) {
+        Some(slot) => (slot.0, std::mem::replace(&mut slot.1, height)),
+        None => return,
+    };
+
+    positions.iter_mut().for_each(|(_, (pos, _))| {
+        if *pos > at {
+            *pos = (*pos + height).saturating_sub(old);
+        }
+    });
+}
+
+/// Number of rows taken by all bars
+fn used_rows() -> u16 {
+    positions()
+        .lock()
+        .unwrap()
+        .values()
+        .map(|&(pos, height)| pos.saturating_add(height))
+        .max()
+        .unwrap_or(0)
+}
+
+#[cfg(all(test, not(feature = "disabled")))]
+mod tests {
+    use std::cmp::max;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{mpsc, Arc};
+    use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+    use super::{
+        active_states, close_in_order, default_template, eta_at, fallback_size, layout_of,
+        lock_globals, max_progress_bars, positions, print_above, print_plain_lines, rate,
+        refresh_interval, sanitize_size, scroll_region, set_default_terminal_size,
+        set_manage_cursor, set_max_progress_bars, set_show_hidden_indicator, sweep, State,
+        DEFAULT_TERMINAL_SIZE, DROP_BUSY, FALLBACK_SIZE, INTERVAL, LINE_RESERVED, MANAGE_CURSOR,
+        MAX_BARS, SHOW_INDICATOR,
+    };
+    use crate::{
+        active_bars, format, render_all, set_default_style, AvanceBar, AvanceIterator, BarOptions,
+        MockClock, PercentPosition, Style, Theme, Total,
+    };
+    use crossterm::style::{Color, Stylize};
+
+    #[test]
+    #[ignore = "benchmark"]
+    fn performance() {
+        let n = 20_000_000;
+
+        let start = Instant::now();
+        for _ in 0..n {}
+        let du = Instant::now().duration_since(start).as_secs_f64();
+        println!("raw: {:.2} it/s", n as f64 / du);
+
+        let start = Instant::now();
+        let pb = AvanceBar::new(n);
+        for _ in pb.with_iter(0..n) {}
+        let du = Instant::now().duration_since(start).as_secs_f64();
+        println!("always check: {:.2} it/s", n as f64 / du);
+
+        let start = Instant::now();
+        let pb = AvanceBar::new(n).with_miniters_auto(true);
+        for _ in pb.with_iter(0..n) {}
+        let du = Instant::now().duration_since(start).as_secs_f64();
+        println!("auto miniters: {:.2} it/s", n as f64 / du);
+        assert_eq!(pb.update_and_get(0), n);
+    }
+
+    #[test]
+    #[ignore = "benchmark"]
+    fn postfix_performance() {
+        let n = 200_000;
+        let pb = AvanceBar::new(n);
+
+        let start = Instant::now();
+        for i in 0..n {
+            pb.set_postfix(format!("i={}", i));
+        }
+        let du = Instant::now().duration_since(start).as_secs_f64();
+        println!("set_postfix: {:.2} it/s", n as f64 / du);
+
+        let start = Instant::now();
+        for i in 0..n {
+            pb.set_postfix_fmt(format_args!("i={}", i));
+        }
+        let du = Instant::now().duration_since(start).as_secs_f64();
+        println!("set_postfix_fmt: {:.2} it/s", n as f64 / du);
+
+        assert!(pb.render_line(80).ends_with(&format!(", i={}]", n - 1)));
+    }
+
+    #[test]
+    fn miniters_auto() {
+        let pb = AvanceBar::new(1000).with_miniters_auto(true);
+        pb.update(1);
+        let next_check = pb.progress.next_check.load(Ordering::Relaxed);
+        assert!(next_check >= 2);
+
+        pb.progress.prev.store(0, Ordering::Relaxed);
+        pb.progress.last.store(0, Ordering::Relaxed);
+        pb.progress.schedule(50, INTERVAL / 2);
+        assert_eq!(pb.progress.next_check.load(Ordering::Relaxed), 150);
+        assert!(pb.progress.skip(149));
+        assert!(!pb.progress.skip(150));
+
+        pb.set_refresh_on_inc(true);
+        assert!(!pb.progress.skip(149));
+    }
+
+    #[test]
+    fn active_bars_after_close() {
+        let pb = AvanceBar::new(100);
+        let id = pb.state.lock().unwrap().id;
+        assert!(active_bars() >= 1);
+        assert!(positions().lock().unwrap().contains_key(&id));
+
+        pb.close();
+        assert!(!positions().lock().unwrap().contains_key(&id));
+    }
+
+    #[test]
+    fn render_without_terminal() {
+        let pb = AvanceBar::new(100).with_desc("render_all");
+        pb.update(30);
+
+        let line = pb.render_line(80);
+        assert!(line.starts_with("render_all:  30%|"));
+        assert!(line.chars().count() <= 80);
+        assert!(!line.contains('\x1b'));
+
+        assert!(render_all(80).iter().any(|l| l.starts_with("render_all:")));
+        pb.close();
+        assert!(!render_all(80).iter().any(|l| l.starts_with("render_all:")));
+    }
+
+    #[test]
+    fn inc_and_get_concurrently() {
+        let pb = AvanceBar::new(4000);
+
+        let mut values: Vec<_> = std::thread::scope(|t| {
+            let handles: Vec<_> = (0..4)
+                .map(|_| t.spawn(|| (0..1000).map(|_| pb.inc_and_get()).collect::<Vec<_>>()))
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|h| h.join().unwrap())
+                .collect()
+        });
+
+        values.sort_unstable();
+        assert_eq!(values, (1..=4000).collect::<Vec<_>>());
+        assert_eq!(pb.update_and_get(0), 4000);
+    }
+
+    #[test]
+    fn write_counts_bytes() {
+        let data = [0u8; 1000];
+        let pb = AvanceBar::new(data.len() as u64);
+
+        let copied = std::io::copy(&mut data.as_slice(), &mut &pb).unwrap();
+        assert_eq!(copied, 1000);
+        assert_eq!(pb.update_and_get(0), 1000);
+    }
+
+    #[test]
+    fn default_style() {
+        let _globals = lock_globals();
+        let previous = default_template().lock().unwrap().style.clone();
+        set_default_style(Style::Balloon);
+        let pb = AvanceBar::new(100);
+        set_default_style(previous.clone());
+
+        assert!(matches!(
+            pb.state.lock().unwrap().template.style,
+            Style::Balloon
+        ));
+        assert_eq!(
+            format!("{:?}", default_template().lock().unwrap().style),
+            format!("{:?}", previous)
+        );
+    }
+
+    #[test]
+    fn truncate_long_postfix() {
+        let pb = AvanceBar::new(100).with_desc("a long description");
+        pb.set_postfix("a very long postfix ".repeat(5));
+        pb.update(50);
+
+        let line = pb.render_line(80);
+        let bar = line.split('|').nth(1).unwrap();
+        assert_eq!(line.chars().count(), 80);
+        assert_eq!(bar.chars().count(), 10);
+        assert!(line.ends_with("…]"));
+        assert!(!line.contains("description"));
+    }
+
+    #[test]
+    fn countdown() {
+        let pb = AvanceBar::new(100)
+            .with_style(Style::ASCII)
+            .with_countdown(true);
+        pb.update(60);
+
+        let line = pb.render_line(80);
+        assert!(line.starts_with(" 40%|######"));
+        assert!(line.contains("| 40/100 ["));
+    }
+
+    #[test]
+    fn raw_rate() {
+        // 100 steps in 10s on average, but 10 steps in the last 0.1s
+        let blended = rate(100, 10.0, 10, 0.1, false);
+        let raw = rate(100, 10.0, 10, 0.1, true);
+        assert!((blended - 37.0).abs() < 1e-9);
+        assert!((raw - 100.0).abs() < 1e-9);
+
+        // no progress since the last refresh
+        assert!((rate(100, 10.0, 0, 0.1, true) - 10.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn for_range_and_slice() {
+        let (pb, iter) = AvanceBar::for_range(0..1000);
+        assert_eq!(pb.total(), Some(1000));
+        assert_eq!(iter.len(), 1000);
+        iter.take(500).for_each(|_| {});
+        assert!(pb.render_line(80).starts_with(" 50%|"));
+
+        let (pb, iter) = AvanceBar::for_range(-5i8..5);
+        assert_eq!(pb.total(), Some(10));
+        assert_eq!(iter.sum::<i8>(), -5);
+
+        let data = vec!['a'; 42];
+        let (pb, iter) = AvanceBar::for_slice(&data);
+        assert_eq!(pb.total(), Some(data.len() as u64));
+        assert_eq!(iter.count(), 42);
+        assert!(pb.render_line(80).starts_with("100%|"));
+    }
+
+    #[test]
+    fn for_file() {
+        let path = std::env::temp_dir().join(format!("avance-{}.data", std::process::id()));
+        std::fs::write(&path, vec![0u8; 4096]).unwrap();
+
+        let mut file = std::fs::File::open(&path).unwrap();
+        let pb = AvanceBar::for_file(&file);
+        assert_eq!(pb.total(), Some(4096));
+        std::io::copy(&mut file, &mut &pb).unwrap();
+        assert!(pb.render_line(80).contains(" 4.00KiB/4.00KiB "));
+        std::fs::remove_file(&path).unwrap();
+
+        #[cfg(target_os = "linux")]
+        {
+            let file = std::fs::File::open("/proc/self/status").unwrap();
+            assert_eq!(AvanceBar::for_file(&file).total(), None);
+        }
+    }
+
+    #[test]
+    fn spawn_group() {
+        let template = AvanceBar::new(100).with_desc("worker");
+        let bars = template.spawn_group(4);
+        for (i, pb) in bars.iter().enumerate() {
+            pb.update(10 * i as u64);
+        }
+
+        // Other tests may create bars in between
+        let positions: Vec<_> = bars.iter().map(|pb| pb.position().unwrap()).collect();
+        assert!(positions.windows(2).all(|w| w[0] < w[1]));
+        for (i, pb) in bars.iter().enumerate() {
+            assert_eq!(pb.total(), Some(100));
+            assert_eq!(pb.update_and_get(0), 10 * i as u64);
+            assert!(pb.render_line(80).starts_with("worker: "));
+        }
+        assert_eq!(template.update_and_get(0), 0);
+    }
+
+    #[test]
+    fn persistence() {
+        let path = std::env::temp_dir().join(format!("avance-{}.progress", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let pb = AvanceBar::new(100).with_persistence(&path);
+        pb.update(40);
+        drop(pb);
+        let saved = std::fs::read_to_string(&path).unwrap();
+        assert!(saved.starts_with("40 "));
+
+        // Resumed from the saved progress, which isn't counted as new
+        let pb = AvanceBar::new(100).with_persistence(&path);
+        assert_eq!(pb.update_and_get(0), 40);
+        assert!(pb.render_line(80).starts_with(" 40%|"));
+        assert!(pb.elapsed() >= Duration::from_nanos(saved[3..].trim().parse().unwrap()));
+        assert_eq!(pb.progress.last.load(Ordering::Acquire), 40);
+
+        // Forgotten once done
+        pb.update(60);
+        pb.close();
+        assert!(!path.exists());
+
+        std::fs::write(&path, "corrupt").unwrap();
+        let pb = AvanceBar::new(100).with_persistence(&path);
+        assert_eq!(pb.update_and_get(0), 0);
+        drop(pb);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn hide_rate() {
+        let pb = AvanceBar::new(100)
+            .with_style(Style::ASCII)
+            .with_show_rate(false)
+            .with_width(40);
+        pb.set_postfix("p");
+        pb.update(50);
+        assert_eq!(
+            pb.render_line(80),
+            " 50%|#####0    | 50/100 [00:00<00:00, p]"
+        );
+
+        pb.set_total(None);
+        assert_eq!(pb.render_line(80), "50it [00:00], p");
+        pb.set_show_rate(true);
+        assert!(pb.render_line(80).contains("it/s"));
+    }
+
+    #[test]
+    fn exhausted_iterators_redraw() {
+        // Far less than a refresh interval
+        let iter = (0..1000).avance();
+        let pb = iter.bar.clone();
+        assert_eq!(iter.sum::<u64>(), 499500);
+        assert_eq!(pb.progress.last.load(Ordering::Acquire), 1000);
+
+        let iter = (0..10).avance();
+        let pb = iter.bar.clone();
+        let mut iter = iter.filtered(|i| i % 2 == 0);
+        assert_eq!(iter.by_ref().count(), 5);
+        assert_eq!(pb.progress.last.load(Ordering::Acquire), 5);
+    }
+
+    #[test]
+    fn hide_after_complete() {
+        let after = Duration::from_millis(20);
+        let pb = AvanceBar::new(10).with_hide_after_complete(after);
+        let unfinished = AvanceBar::new(10).with_hide_after_complete(after);
+        let unbounded = AvanceBar::new(0).with_hide_after_complete(after);
+        unbounded.set_total(None);
+
+        pb.update(10);
+        unfinished.update(9);
+        unbounded.update(10);
+        assert!(pb.position().is_some());
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while pb.position().is_some() && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert!(pb.position().is_none());
+        assert!(unfinished.position().is_some());
+        assert!(unbounded.position().is_some());
+    }
+
+    #[test]
+    fn zero_elapsed() {
+        assert!(rate(0, 0.0, 0, 0.0, false).is_nan());
+        assert!(rate(10, 0.0, 10, 0.0, true).is_nan());
+        // no time since the last refresh
+        assert!((rate(100, 10.0, 10, 0.0, false) - 10.0).abs() < 1e-9);
+
+        // A clock which hasn't ticked since the bar was created
+        let pb = AvanceBar::new(100);
+        pb.progress.offset.store(i64::MIN / 2, Ordering::Relaxed);
+        pb.progress.update();
+        pb.update(10);
+        let line = pb.render_line(80);
+        assert!(line.contains(" 10/100 [00:00<?, ?it/s]"));
+        assert!(!line.contains("NaN") && !line.contains("inf"));
+
+        pb.set_total(None);
+        assert!(pb.render_line(80).starts_with("10it [00:00, ?it/s]"));
+    }
+
+    #[test]
+    fn update_zero() {
+        let clock = Arc::new(MockClock::new());
+        let pb = AvanceBar::new(100).with_clock(clock.clone());
+        let drawn = || pb.progress.last.load(Ordering::Relaxed);
+        pb.update(10);
+        pb.update(0);
+        // Not redrawn within the refresh interval
+        assert_eq!(drawn(), 0);
+
+        clock.advance(Duration::from_millis(150));
+        pb.update(0);
+        assert_eq!(drawn(), 10);
+        pb.refresh();
+        assert_eq!(pb.update_and_get(0), 10);
+    }
+
+    #[test]
+    fn theme() {
+        let pb = AvanceBar::new(100)
+            .with_desc("theme")
+            .with_theme(Theme::Nord);
+        pb.update(50);
+
+        let state = pb.state.lock().unwrap();
+        assert!(!state.render(80, false).contains('\x1b'));
+        assert!(state.render(80, true).contains('\x1b'));
+    }
+
+    #[test]
+    fn from_config() {
+        let options = BarOptions {
+            total: Some(2000),
+            style: Some(Style::ASCII),
+            width: Some(100),
+            desc: Some("config".into()),
+            postfix: Some("ok".into()),
+            unit_scale: true,
         };
+        let pb = AvanceBar::from_config(options.clone());
+        pb.update(1000);
+        let line = pb.render_line(120);
+        assert!(line.starts_with("config:  50%|#####"));
+        assert!(line.contains(" 1.00k/2.00k ["));
+        assert!(line.ends_with(", ok]"));
+        assert_eq!(line.chars().count(), 100);
+
+        let pb = AvanceBar::from_config(BarOptions::default());
+        pb.update(10);
+        assert!(pb.render_line(80).starts_with("10it ["));
+    }
+
+    #[test]
+    fn close_with_message() {
+        let _lines = print_plain_lines();
+        let output = crate::testing::capture(|| {
+            let pb = AvanceBar::new(4).with_desc("download");
+            pb.update(4);
+            pb.close_with("Downloaded 4 files");
+            // Only the first closing counts
+            pb.close_with("ignored");
+            assert_eq!(pb.render_line(80), "Downloaded 4 files");
+        });
+        assert_eq!(output, "Downloaded 4 files\n");
+
+        crate::testing::capture(|| {
+            let pb = AvanceBar::new(4).with_multiline(true);
+            pb.close_with(format_args!("{}", "done"));
+            assert_eq!(pb.render_line(80), "done\n");
+            pb.set_multiline(false);
+            assert_eq!(pb.render_line(2), "d…");
+        });
+    }
+
+    #[test]
+    fn auto_close() {
+        let _lines = print_plain_lines();
+        let pb = AvanceBar::new(10)
+            .with_desc("auto-closed")
+            .with_auto_close(true);
+        let output = crate::testing::capture(|| {
+            pb.update(4);
+            assert!(pb.position().is_some());
+            pb.update(6);
+        });
+        assert!(pb.position().is_none());
+
+        let lines: Vec<_> = output.lines().collect();
+        // Closed right away, and only once
+        assert!(lines.last().unwrap().starts_with("auto-closed: 100%|"));
+        assert_eq!(lines.iter().filter(|line| line.contains("100%")).count(), 1);
+
+        // Unbounded bars are never complete
+        crate::testing::capture(|| {
+            let pb = AvanceBar::from_total(None).with_auto_close(true);
+            pb.update(u64::MAX / 2);
+            assert!(pb.position().is_some());
+        });
+    }
+
+    #[test]
+    fn secondary_fill() {
+        let pb = AvanceBar::new(100)
+            .with_style(Style::ASCII)
+            .with_show_rate(false);
+        pb.update(30);
+        pb.set_secondary(60);
+        assert_eq!(
+            pb.render_line(60),
+            " 30%|#########9---------              | 30/100 [00:00<00:00]"
+        );
+
+        // Behind the progress
+        pb.set_secondary(10);
+        assert_eq!(
+            pb.render_line(60),
+            " 30%|#########9                       | 30/100 [00:00<00:00]"
+        );
+
+        pb.set_style(Style::Block);
+        pb.set_secondary(60);
+        assert!(pb
+            .render_line(60)
+            .contains("|█████████▉░░░░░░░░░              |"));
+    }
+
+    #[test]
+    fn verbose() {
+        let pb = AvanceBar::new(100)
+            .with_style(Style::ASCII)
+            .with_desc("task")
+            .with_compact(true);
+        pb.update(30);
+        assert_eq!(pb.render_line(80).lines().count(), 1);
+
+        pb.set_verbose(true);
+        let rendered = pb.render_line(80);
+        let lines: Vec<_> = rendered.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("task:  30%|#"));
+        assert!(lines[1].starts_with("30/100 [00:00<00:00, "));
+        assert!(lines[2].starts_with("30/100 | "));
+        assert!(lines[2].contains("it/s on average | ETA 00:00 (@"));
+        assert_eq!(pb.state.lock().unwrap().height(), 3);
+
+        pb.set_verbose(false);
+        assert_eq!(pb.render_line(80).lines().count(), 1);
+        let pb = AvanceBar::from_total(None).with_verbose(true);
+        let details = pb.render_line(80).lines().nth(2).unwrap().to_string();
+        assert!(details.starts_with("0/? | ") && details.ends_with(" | ETA ?"));
+    }
+
+    #[test]
+    fn format_without_locking() {
+        struct Position(AvanceBar);
+
+        impl std::fmt::Display for Position {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, "at {:?}", self.0.position())
+            }
+        }
+
+        impl From<Position> for std::borrow::Cow<'static, str> {
+            fn from(pos: Position) -> Self {
+                pos.to_string().into()
+            }
+        }
+
+        let pb = AvanceBar::new(100);
+        let (tx, rx) = std::sync::mpsc::channel();
+        let handle = pb.clone();
+        std::thread::spawn(move || {
+            handle.set_postfix(Position(handle.clone()));
+            handle.set_desc(Position(handle.clone()));
+            crate::println(Position(handle.clone()));
+            handle.close_with(Position(handle.clone()));
+            tx.send(()).unwrap();
+        });
+
+        assert!(rx.recv_timeout(Duration::from_secs(5)).is_ok());
+        assert!(pb.render_line(80).starts_with("at Some("));
+    }
+
+    #[test]
+    fn set_fraction() {
+        let pb = AvanceBar::new_percentage().with_style(Style::ASCII);
+        for (fraction, pct) in [
+            (0.0, "  0%|"),
+            (0.5, " 50%|"),
+            (1.0, "100%|"),
+            (0.25, " 25%|"),
+        ] {
+            pb.set_fraction(fraction);
+            assert!(pb.render_line(80).starts_with(pct));
+        }
+        pb.set_fraction(f64::NAN);
+        assert!(pb.render_line(80).starts_with("  0%|"));
+        pb.set_fraction(7.0);
+        assert!(pb.render_line(80).contains(" 100/100 "));
+
+        let pb = AvanceBar::new(3);
+        pb.set_fraction(0.5);
+        assert!(pb.render_line(80).contains(" 2/3 "));
+    }
+
+    #[test]
+    fn tracking_counter() {
+        let counter = Arc::new(AtomicU64::new(0));
+        let pb = AvanceBar::tracking(400, Arc::clone(&counter)).with_style(Style::ASCII);
+        std::thread::scope(|t| {
+            for _ in 0..4 {
+                t.spawn(|| {
+                    for _ in 0..100 {
+                        counter.fetch_add(1, Ordering::Relaxed);
+                    }
+                });
+            }
+        });
+        pb.refresh();
+        assert!(pb.render_line(80).contains(" 400/400 "));
+
+        counter.store(100, Ordering::Relaxed);
+        assert_eq!(pb.update_and_get(0), 100);
+        assert!(pb.render_line(80).contains(" 100/400 "));
+    }
+
+    #[test]
+    fn eta_absolute() {
+        let pb = AvanceBar::new(100)
+            .with_style(Style::ASCII)
+            .with_eta_absolute(true);
+        pb.progress.offset.store(100_000_000_000, Ordering::Relaxed);
+        pb.update(50);
+
+        // Half way through in 100s, so done in another 100s
+        let expected: Vec<_> = (0..2)
+            .map(|i| {
+                let done = SystemTime::now() + Duration::from_secs(100 + i);
+                format!("<@{},", format::format_clock(done, 0))
+            })
+            .collect();
+        let line = pb.render_line(80);
+        assert!(expected.iter().any(|eta| line.contains(eta.as_str())));
+
+        assert_eq!(eta_at(UNIX_EPOCH, 3600.0 * 6.0 + 900.0), "@06:15");
+        assert_eq!(eta_at(UNIX_EPOCH, f64::INFINITY), "@00:00");
+
+        let pb = AvanceBar::from_total(None).with_eta_absolute(true);
+        assert!(pb.render_line(80).starts_with("0it [00:00<?, "));
+    }
+
+    #[test]
+    fn update_signed() {
+        let pb = AvanceBar::new(100)
+            .with_style(Style::ASCII)
+            .with_show_rate(false);
+        pb.update_signed(60);
+        pb.update_signed(-20);
+        assert_eq!(pb.update_and_get(0), 40);
+        assert_eq!(
+            pb.render_line(60),
+            " 40%|#############2                   | 40/100 [00:00<00:00]"
+        );
+        assert!(pb.state.lock().unwrap().rate() >= 0.0);
+
+        pb.update_signed(-100);
+        assert_eq!(pb.update_and_get(0), 0);
+        assert_eq!(
+            pb.render_line(60),
+            "  0%|0                                     | 0/100 [00:00<?]"
+        );
+
+        pb.update_signed(30);
+        assert_eq!(
+            pb.render_line(60),
+            " 30%|#########9                       | 30/100 [00:00<00:00]"
+        );
+    }
+
+    #[test]
+    fn set_total() {
+        let pb = AvanceBar::new(0);
+        pb.set_total(None);
+        pb.update(40);
+        assert!(pb.render_line(80).starts_with("40it ["));
+
+        pb.set_total(Some(80));
+        let line = pb.render_line(80);
+        assert!(line.starts_with(" 50%|"));
+        assert!(!line.contains("it ["));
+
+        pb.set_total(None);
+        assert!(!pb.render_line(80).contains('%'));
+    }
+
+    #[test]
+    fn refresh_on_inc() {
+        let pb = AvanceBar::new(100);
+        pb.progress.update();
+        assert!(!pb.progress.ready(0, pb.progress.now()));
+
+        pb.set_refresh_on_inc(true);
+        assert!(pb.progress.ready(0, pb.progress.now()));
+    }
+
+    #[test]
+    fn adaptive_refresh() {
+        let pb = AvanceBar::new(100);
+        pb.progress.update();
+        // 60ms since the last redraw, between half the interval and the interval
+        let now = pb.progress.prev.load(Ordering::Acquire) + 60_000_000;
+        assert!(!pb.progress.ready(96, now));
+
+        pb.set_adaptive_refresh(true);
+        assert!(!pb.progress.ready(50, now));
+        assert!(pb.progress.ready(95, now));
+        assert!(pb.progress.ready(100, now));
+
+        // Follows the total
+        pb.set_total(Some(1000));
+        assert!(!pb.progress.ready(100, now));
+        pb.set_total(None);
+        assert!(!pb.progress.ready(1000, now));
+    }
+
+    #[test]
+    fn zero_terminal_size() {
+        let fallback = DEFAULT_TERMINAL_SIZE;
+        assert_eq!(sanitize_size(Ok((0, 0)), fallback), (80, 24));
+        assert_eq!(sanitize_size(Ok((0, 30)), fallback), (80, 30));
+        assert_eq!(sanitize_size(Ok((120, 30)), fallback), (120, 30));
+
+        let fallback = (100, 24);
+        assert_eq!(sanitize_size(Ok((0, 0)), fallback), (100, 24));
+        assert_eq!(
+            sanitize_size(Err(std::io::ErrorKind::Other.into()), fallback),
+            (100, 24)
+        );
+
+        let _globals = lock_globals();
+        let previous = FALLBACK_SIZE.load(Ordering::Relaxed);
+        set_default_terminal_size(100, 0);
+        let changed = fallback_size();
+        FALLBACK_SIZE.store(previous, Ordering::Relaxed);
+        assert_eq!(changed, (100, 24));
+
+        let pb = AvanceBar::new(100);
+        pb.update(50);
+        let line = pb.render_line(sanitize_size(Ok((0, 0)), DEFAULT_TERMINAL_SIZE).0);
+        assert_eq!(line.chars().count(), 80);
+    }
+
+    #[test]
+    fn dumb_mode() {
+        let pb = AvanceBar::new(100);
+        let state = pb.state.lock().unwrap();
+        let mut output = Vec::new();
+
+        pb.progress.inc(35);
+        state.log(10, false, &mut output).unwrap();
+        pb.progress.inc(3);
+        state.log(10, false, &mut output).unwrap();
+        state.log(10, true, &mut output).unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with(" 35%|"));
+        assert!(lines[1].starts_with(" 38%|"));
+        assert!(!output.contains('\x1b'));
+    }
+
+    #[test]
+    fn print_above_bars() {
+        let (mut bars, mut out) = (Vec::new(), Vec::new());
+        print_above("message", &mut bars, &mut out).unwrap();
+
+        assert!(!bars.is_empty());
+        assert_eq!(out, b"message\n");
+    }
+
+    #[test]
+    fn total_of_integers() {
+        let v = [0; 30];
+        let pb = AvanceBar::new(0).with_total(v.len());
+        pb.update(15);
+        assert!(pb.render_line(80).contains(" 15/30 "));
+
+        let pb = AvanceBar::new(0).with_total(40u32);
+        pb.update(10);
+        assert!(pb.render_line(80).contains(" 10/40 "));
+
+        let pb = AvanceBar::new(0).with_total(50i64);
+        pb.update(10);
+        assert!(pb.render_line(80).contains(" 10/50 "));
+
+        assert_eq!((-1i64).into_total(), 0);
+        assert_eq!(u128::MAX.into_total(), u64::MAX);
+    }
+
+    #[test]
+    fn read_globals() {
+        let _globals = lock_globals();
+        let (max_bars, indicator) = (
+            MAX_BARS.load(Ordering::Relaxed),
+            SHOW_INDICATOR.load(Ordering::Relaxed),
+        );
+        set_max_progress_bars(3);
+        assert_eq!(max_progress_bars(), Some(3));
+        assert_eq!(max_progress_bars(), Some(3));
+        assert_eq!(refresh_interval().as_millis(), 100);
+
+        set_max_progress_bars(2);
+        set_show_hidden_indicator(false);
+        let mut output = Vec::new();
+        for pos in 0..5 {
+            let pb = AvanceBar::new(10).with_desc("overflow");
+            pb.state
+                .lock()
+                .unwrap()
+                .draw(Some(pos), &mut output)
+                .unwrap();
+        }
+        MAX_BARS.store(max_bars, Ordering::Relaxed);
+        SHOW_INDICATOR.store(indicator, Ordering::Relaxed);
+
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output.matches("overflow: ").count(), 2);
+        assert!(!output.contains("more hidden"));
+    }
+
+    #[test]
+    fn max_bars_layout() {
+        // N = 1
+        assert_eq!(layout_of(Some(1), 1, 24, true), (1, false));
+        assert_eq!(layout_of(Some(1), 2, 24, true), (2, true));
+        assert_eq!(layout_of(Some(1), 2, 24, false), (1, false));
+
+        // N = 3
+        assert_eq!(layout_of(Some(3), 2, 24, true), (3, false));
+        assert_eq!(layout_of(Some(3), 3, 24, true), (3, false));
+        assert_eq!(layout_of(Some(3), 5, 24, true), (4, true));
+        assert_eq!(layout_of(Some(3), 5, 2, true), (2, true));
+
+        // Zero bars, and no limit
+        assert_eq!(layout_of(Some(0), 0, 24, true), (0, false));
+        assert_eq!(layout_of(Some(0), 2, 24, false), (0, false));
+        assert_eq!(layout_of(None, 100, 24, true), (24, true));
+    }
+
+    #[test]
+    fn sticky_scroll_region() {
+        let mut grow = Vec::new();
+        scroll_region(0, 4, 24, &mut grow).unwrap();
+        let grow = String::from_utf8(grow).unwrap();
+        assert!(grow.starts_with("\n\n\n\n"));
+        assert!(grow.contains("\x1b[1;20r"));
+
+        let mut shrink = Vec::new();
+        scroll_region(4, 0, 24, &mut shrink).unwrap();
+        let shrink = String::from_utf8(shrink).unwrap();
+        assert!(shrink.contains("\x1b[r"));
+
+        let mut unchanged = Vec::new();
+        scroll_region(2, 2, 24, &mut unchanged).unwrap();
+        assert!(unchanged.is_empty());
+    }
+
+    #[test]
+    fn idle_time() {
+        let pb = AvanceBar::new(100);
+        pb.inc();
+        std::thread::sleep(Duration::from_millis(50));
+        let idle = pb.idle_time();
+        assert!(idle >= Duration::from_millis(50));
+
+        std::thread::sleep(Duration::from_millis(10));
+        assert!(pb.idle_time() > idle);
+
+        pb.update(0);
+        assert!(pb.idle_time() > idle);
+        pb.inc();
+        assert!(pb.idle_time() < idle);
+    }
 
-        let ncols = terminal_size().0;
-        let nrows = nrows();
-        if pos >= nrows {
-            return Ok(());
+    #[test]
+    fn verify_total() {
+        let v = [0; 10];
+        let pb = AvanceBar::new(10).with_verify_total(true);
+        for chunk in v.chunks(4).take(2) {
+            for _ in pb.with_iter(chunk) {}
         }
+        let mut state = pb.state.lock().unwrap();
+        assert_eq!(state.planned, 8);
+        assert!(state.finish().unwrap().contains("closed at 8/10"));
+        assert!(state.finish().is_none());
+        drop(state);
 
-        let msg = if pos == nrows - 1 {
-            "... (more hidden) ...".to_string()
-        } else {
-            format!("{}", self)
-        };
-        let msg = format!("{:1$}", msg, ncols as usize);
+        let output = crate::testing::capture(|| {
+            let pb = AvanceBar::new(10)
+                .with_verify_total(true)
+                .with_desc("chunks");
+            assert!(pb.state.lock().unwrap().plan(6).is_none());
+            let warning = pb.state.lock().unwrap().plan(6).unwrap();
+            assert!(warning.contains("[chunks] wrapped iterators have 12 items"));
+        });
+        assert!(output.contains("[chunks] closed at 0/10"));
 
-        if pos != 0 {
-            target
-                .queue(Print("\n".repeat(pos as usize)))?
-                .queue(Print(msg))?
-                .queue(MoveUp(pos))?
-                .queue(MoveToColumn(ncols))?
-        } else {
-            target.queue(MoveToColumn(0))?.queue(Print(msg))?
+        let pb = AvanceBar::new(10).with_verify_total(true);
+        for chunk in v.chunks(3) {
+            for _ in pb.with_iter(chunk) {}
         }
-        .flush()
+        assert!(pb.state.lock().unwrap().finish().is_none());
     }
 
-    fn draw_to_stderr(&self, pos: Option<u16>) -> Result<()> {
-        self.draw(pos, &mut stderr().lock())
-    }
+    #[test]
+    fn finalize_in_position_order() {
+        let threads: Vec<_> = (0..4)
+            .map(|i| {
+                std::thread::spawn(move || {
+                    let pb = AvanceBar::new(10).with_desc(format!("t{}", i));
+                    pb.update(i + 1);
+                    pb
+                })
+            })
+            .collect();
+        let bars: Vec<_> = threads.into_iter().map(|t| t.join().unwrap()).collect();
+        bars[2].close();
 
-    fn drawable(&self) -> bool {
-        // is_terminal is stable on 1.70.0
-        stderr().is_tty() && self.try_get_pos().is_some()
+        let mut expected: Vec<_> = bars
+            .iter()
+            .enumerate()
+            .filter_map(|(i, pb)| pb.position().map(|pos| (pos, i)))
+            .collect();
+        expected.sort_unstable();
+
+        let ids: Vec<_> = bars.iter().map(|pb| pb.state.lock().unwrap().id).collect();
+        let states = active_states()
+            .into_iter()
+            .filter(|state| ids.contains(&state.lock().unwrap().id))
+            .collect();
+        let lines = close_in_order(states);
+
+        assert_eq!(lines.len(), 3);
+        for (line, (_, i)) in lines.iter().zip(&expected) {
+            assert!(line.starts_with(&format!("t{}: ", i)));
+            assert!(line.contains(&format!(" {}/10 ", i + 1)));
+        }
+        assert!(bars.iter().all(|pb| pb.position().is_none()));
     }
 
-    fn close(&mut self) -> Result<()> {
-        if !self.drawable() {
-            // already closed
-            return Ok(());
+    #[test]
+    fn final_icons() {
+        let complete = AvanceBar::new(10)
+            .with_success_icon('✓')
+            .with_fail_icon('✗');
+        let incomplete = AvanceBar::new(10).with_template_of(&complete);
+        let unbounded = AvanceBar::new(0).with_template_of(&complete);
+        unbounded.set_total(None);
+
+        complete.update(10);
+        incomplete.update(5);
+        for pb in [&complete, &incomplete, &unbounded] {
+            assert!(!pb.render_line(80).starts_with(['✓', '✗']));
+            pb.state.lock().unwrap().closed = true;
         }
 
-        // Close the current bar and move up other bars
-        reposition(self.id);
+        let line = complete.render_line(80);
+        assert!(line.starts_with("✓ 100%|"));
+        assert_eq!(line.chars().count(), 80);
+        assert!(incomplete.render_line(80).starts_with("✗  50%|"));
+        assert!(unbounded.render_line(80).starts_with("✓ 0it"));
+    }
 
-        let mut target = stderr().lock();
+    #[test]
+    fn indeterminate_frames() {
+        let pb = AvanceBar::new(0)
+            .with_style(Style::ASCII)
+            .with_indeterminate(true)
+            .with_desc("scan");
+        pb.set_total(None);
+        pb.update(7);
 
-        // force update (only displaying average its)
-        self.progress.update();
-        let _ = self.draw(Some(0), &mut target);
+        let frame = |i| {
+            pb.state.lock().unwrap().frame.set(i);
+            pb.render_line(80)
+        };
+        let (first, second) = (frame(0), frame(1));
+        assert!(first.starts_with("scan: 7it |###       "));
+        assert!(second.starts_with("scan: 7it | ###      "));
+        assert_eq!(first.chars().count(), 80);
+        assert_eq!(second.chars().count(), 80);
 
-        // Move cursor to the end of the next line
-        let ncols = terminal_size().0;
+        assert_eq!(sweep(10, 7), (7, 3, 0));
+        assert_eq!(sweep(10, 8), (6, 3, 1));
+        assert_eq!(sweep(2, 5), (0, 2, 0));
+    }
 
-        target.queue(Print('\n'))?;
-        if !is_finished() {
-            // only do this when some bars are still in progress
-            target.queue(MoveToColumn(ncols))?;
+    #[test]
+    fn fixed_ncols() {
+        let pb = AvanceBar::new(1000)
+            .with_ncols(80)
+            .with_desc("a very long description");
+        let mut output = Vec::new();
+        for i in 1..4 {
+            // Not drawn in between, which would print lines of the default step
+            pb.progress.inc(250);
+            let mut state = pb.state.lock().unwrap();
+            state.template.postfix = Some(format!("round {}", i).into());
+            state.log(25, false, &mut output).unwrap();
         }
-        target.flush()
+
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output.lines().count(), 3);
+        assert!(output.lines().all(|line| line.chars().count() == 80));
     }
 
-    /// Sweep a progress bar from the terminal.
-    /// Useful when a progress bar's width was changed.
-    fn clear(&self) -> Result<()> {
-        if !self.drawable() {
-            return Ok(());
-        }
+    #[test]
+    fn timer() {
+        let pb = AvanceBar::timer(Duration::from_millis(50));
+        assert_eq!(pb.state.lock().unwrap().total, Some(50));
+        assert!(!pb.tick_by_time());
 
-        let mut target = stderr().lock();
-        let pos = self.get_pos();
-        let nrows = nrows();
-        if pos >= nrows {
-            return Ok(());
-        }
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(pb.tick_by_time());
+        assert_eq!(pb.update_and_get(0), 50);
+    }
 
-        if pos != 0 {
-            target
-                .queue(Print("\n".repeat(pos as usize)))?
-                .queue(Clear(ClearType::CurrentLine))?
-                .queue(MoveUp(pos))?
-        } else {
-            target.queue(Clear(ClearType::CurrentLine))?
-        }
-        .flush()
+    #[test]
+    fn percent_precision() {
+        let pb = AvanceBar::new(3).with_percent_precision(1);
+        pb.inc();
+        let line = pb.render_line(80);
+        assert!(line.starts_with(" 33.3%|"));
+        assert_eq!(line.chars().count(), 80);
+
+        pb.set_percent_precision(2);
+        assert!(pb.render_line(80).starts_with(" 33.33%|"));
+
+        pb.update(2);
+        assert!(pb.render_line(80).starts_with("100.00%|"));
+        pb.set_percent_precision(0);
+        assert!(pb.render_line(80).starts_with("100%|"));
     }
 
-    fn try_get_pos(&self) -> Option<Pos> {
-        let positions = positions().lock().unwrap();
-        positions.get(&self.id).copied()
+    #[test]
+    fn unchanged_layout() {
+        let pb = AvanceBar::new(100).with_width(80).with_style(Style::Block);
+        let mut state = pb.state.lock().unwrap();
+
+        // Re-setting the same width must not clear the bar
+        assert!(!state.resize(80));
+        assert!(state.resize(60));
+        assert_eq!(state.template.width, Some(60));
+
+        assert!(!state.restyle(Style::Block));
+        assert!(!state.restyle(Style::Custom(Style::Block.as_ref().to_string().into())));
+        assert!(state.restyle(Style::ASCII));
     }
 
-    fn get_pos(&self) -> Pos {
-        self.try_get_pos().unwrap()
+    #[test]
+    fn shared_start_time() {
+        let job = Instant::now() - Duration::from_secs(2);
+        let pb1 = AvanceBar::new(100).with_start_time(job);
+        let pb2 = AvanceBar::new(100).with_start_time(job);
+
+        let (elapsed1, elapsed2) = (pb1.elapsed(), pb2.elapsed());
+        assert!(elapsed1 >= Duration::from_secs(2));
+        assert!(elapsed2 >= elapsed1);
+        assert!(elapsed2 - elapsed1 < Duration::from_millis(50));
+        assert!(pb1.idle_time() < Duration::from_secs(1));
+        assert!(pb1.render_line(80).contains("[00:02<"));
+
+        let future = AvanceBar::new(100).with_start_time(Instant::now() + Duration::from_secs(60));
+        assert!(future.elapsed() < Duration::from_secs(1));
     }
-}
 
-impl Display for State {
-    fn fmt(&self, fmt: &mut Formatter<'_>) -> std::fmt::Result {
-        use format::*;
+    #[test]
+    fn sparkline() {
+        let pb = AvanceBar::new(100).with_sparkline(4);
+        let mut state = pb.state.lock().unwrap();
+        state.record_rate(1.0);
+        state.record_rate(2.0);
+        assert!(state.render(80, false).contains("it/s   ▅█]"));
 
-        let elapsed = self.progress.begin.elapsed().as_secs_f64();
-        let desc = self
-            .template
-            .desc
-            .as_ref()
-            .map_or_else(String::new, |desc| format!("{}: ", desc));
-        let postfix = self
-            .template
-            .postfix
-            .as_ref()
-            .map_or_else(String::new, |p| format!(", {}", p));
+        for rate in [4.0, 8.0, 0.0, f64::INFINITY] {
+            state.record_rate(rate);
+        }
+        let line = state.render(80, false);
+        assert!(line.contains("it/s ▃▅█▁]"));
+        assert_eq!(line.chars().count(), 80);
+    }
 
-        let terminal_width = terminal::size().map_or(80, |(c, _)| c);
-        let width = self
-            .template
-            .width
-            .map_or(terminal_width, |w| min(w, terminal_width));
+    #[test]
+    fn shared_by_threads() {
+        let pb = AvanceBar::new(8 * 10_000);
+        std::thread::scope(|t| {
+            for _ in 0..8 {
+                t.spawn(|| {
+                    for _ in 0..10_000 {
+                        pb.inc();
+                    }
+                });
+            }
+        });
+        assert_eq!(pb.update_and_get(0), 8 * 10_000);
+    }
 
-        let n = self.progress.n.load(Ordering::Relaxed);
-        let last_n = self.progress.last.load(Ordering::Relaxed);
-        let since_last = self.progress.since_last() as f64 / 1e9;
+    #[test]
+    fn percent_position() {
+        let pb = AvanceBar::new(100).with_desc("task");
+        pb.update(73);
+        assert!(pb.render_line(80).starts_with("task:  73%|"));
+
+        pb.set_percent_position(PercentPosition::BeforeDesc);
+        let line = pb.render_line(80);
+        assert!(line.starts_with(" 73% task: |"));
+        assert_eq!(line.chars().count(), 80);
+
+        // The description is still truncated first
+        pb.set_desc("a".repeat(100));
+        let line = pb.render_line(80);
+        assert!(line.starts_with(" 73% aaa"));
+        assert_eq!(line.chars().count(), 80);
+    }
 
-        // smoothing
-        let factor = 0.7;
-        let its = match n - last_n {
-            0 => n as f64 / elapsed,
-            gap => (n as f64 / elapsed) * factor + (gap as f64 / since_last) * (1.0 - factor),
+    #[test]
+    fn taskbar_progress() {
+        let draw = |pb: &AvanceBar| {
+            let mut out = Vec::new();
+            let _ = pb.state.lock().unwrap().draw(Some(0), &mut out);
+            String::from_utf8(out).unwrap()
         };
 
-        let time = format_time(elapsed as u64);
+        let pb = AvanceBar::new(200);
+        assert!(!draw(&pb).contains("\x1b]9;4"));
 
-        match self.total {
-            None => fmt.write_fmt(format_args!(
-                "{}{}it [{}, {:.02}it/s]{}",
-                desc, n, time, its, postfix
-            )),
+        pb.set_taskbar_progress(true);
+        assert!(draw(&pb).starts_with("\x1b]9;4;1;0\x07"));
+        pb.update(99);
+        assert!(draw(&pb).starts_with("\x1b]9;4;1;49\x07"));
+        pb.update(101);
+        assert!(draw(&pb).starts_with("\x1b]9;4;1;100\x07"));
+        pb.close();
+        assert!(draw(&pb).starts_with("\x1b]9;4;0\x07"));
 
-            Some(total) => {
-                let pct = (n as f64 / total as f64).clamp(0.0, 1.0);
-                let eta = match n {
-                    0 => String::from("?"),
-                    _ => format_time((elapsed / pct * (1. - pct)) as u64),
-                };
+        let pb = AvanceBar::new(0).with_taskbar_progress(true);
+        pb.set_total(None);
+        assert!(draw(&pb).starts_with("\x1b]9;4;3\x07"));
+    }
 
-                let l_bar = format!("{}{:>3}%|", desc, (100.0 * pct) as u64);
-                let r_bar = match self.template.unit_scale {
-                    true => format!(
-                        "| {}/{} [{}<{}, {:.02}it/s{}]",
-                        format_sizeof(n),
-                        format_sizeof(total),
-                        time,
-                        eta,
-                        its,
-                        postfix
-                    ),
-                    false => format!(
-                        "| {}/{} [{}<{}, {:.02}it/s{}]",
-                        n, total, time, eta, its, postfix
-                    ),
-                };
-                let limit = (width as usize).saturating_sub(l_bar.len() + r_bar.len());
+    #[test]
+    fn minimal_redraws() {
+        // The cursor may be left unmanaged by another test
+        let _globals = lock_globals();
+        let draw = |pb: &AvanceBar, pos| {
+            let mut out = Vec::new();
+            let _ = pb.state.lock().unwrap().draw(Some(pos), &mut out);
+            String::from_utf8(out).unwrap()
+        };
+
+        // A single bar is only overwritten in place
+        let pb = AvanceBar::new(100);
+        let out = draw(&pb, 0);
+        assert!(out.starts_with("\r  0%|"));
+        assert!(!out.contains('\x1b'));
 
-                let style: Vec<_> = self.template.style.as_ref().chars().collect();
+        // The cursor goes back up once for all rows of a bar
+        let pb = AvanceBar::new(100).with_multiline(true);
+        let out = draw(&pb, 2);
+        assert!(out.starts_with("\n\n  0%|"));
+        assert_eq!(out.matches('\n').count(), 3);
+        assert_eq!(out.matches("\x1b[3A").count(), 1);
+        assert_eq!(out.matches('\x1b').count(), 2);
+    }
 
-                let filled = style[0];
-                let (background, in_progress) = style[1..].split_last().unwrap();
+    #[test]
+    fn unmanaged_cursor() {
+        let pb1 = AvanceBar::new(100);
+        let pb2 = AvanceBar::new(100);
+        let draw = |pb: &AvanceBar, pos| {
+            let mut out = Vec::new();
+            let _ = pb.state.lock().unwrap().draw(Some(pos), &mut out);
+            String::from_utf8(out).unwrap()
+        };
 
-                let m = in_progress.len();
-                let k = ((limit as f64 * pct) * m as f64) as usize;
-                let n_filled = k / m;
-                let current = k % m;
+        let _globals = lock_globals();
+        let managed = MANAGE_CURSOR.load(Ordering::Relaxed);
+        set_manage_cursor(false);
+        let top = draw(&pb1, 0);
+        let below = draw(&pb2, 1);
+        let mut printed = Vec::new();
+        print_above("message", &mut printed, &mut Vec::new()).unwrap();
+        MANAGE_CURSOR.store(managed, Ordering::Relaxed);
 
-                let mut bar = filled.to_string().repeat(n_filled);
+        assert!(top.starts_with('\r'));
+        assert!(!top.contains('\x1b'));
+        assert!(below.is_empty());
+        assert_eq!(printed, b"\r\x1b[2K");
+    }
 
-                if n_filled < limit {
-                    bar.push(in_progress[current]);
-                }
+    #[test]
+    fn extreme_values() {
+        // xorshift, to avoid depending on a random number generator
+        let mut seed = 0x2545_f491_4f6c_dd1d_u64;
+        let mut random = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+        let edges = [0, 1, u64::MAX / 2, u64::MAX - 1, u64::MAX];
 
-                // Unicode width is not considered at the moment
-                if n_filled + 1 < limit {
-                    let n_padding = limit - n_filled - 1;
-                    let padding = background.to_string().repeat(n_padding);
+        for i in 0..1000 {
+            let total = match i % 3 {
+                0 => edges[random() as usize % edges.len()],
+                _ => random() >> (random() % 64),
+            };
+            let n = match i % 4 {
+                0 => edges[random() as usize % edges.len()],
+                _ => random() >> (random() % 64),
+            };
+            let width = (random() % 400) as u16;
 
-                    bar.push_str(&padding);
-                }
+            let pb = AvanceBar::new(total)
+                .with_style(Style::Block)
+                .with_unit_scale(i % 2 == 0)
+                .with_countdown(i % 5 == 0)
+                .with_taskbar_progress(i % 7 == 0);
+            pb.update(n);
+            // The progress is behind the last refresh, as if it was reset
+            pb.progress.last.store(n.wrapping_add(1), Ordering::Relaxed);
 
-                fmt.write_fmt(format_args!("{}{}{}", l_bar, bar, r_bar))
+            let line = pb.render_line(width);
+            assert!(line.chars().count() <= max(width as usize, 120), "{}", line);
+            if width >= 120 {
+                assert_eq!(line.chars().count(), width as usize, "{}", line);
             }
+            let state = pb.state.lock().unwrap();
+            state.draw(Some(0), &mut Vec::new()).unwrap();
         }
+
+        let pb = AvanceBar::new(0);
+        assert!(pb.render_line(80).starts_with("100%|"));
+
+        let pb = AvanceBar::new(u64::MAX).with_taskbar_progress(true);
+        pb.update(u64::MAX / 2);
+        let seq = pb.state.lock().unwrap().taskbar_seq();
+        assert_eq!(seq.as_deref(), Some("\x1b]9;4;1;49\x07"));
+        pb.update(u64::MAX / 2 + 1);
+        let seq = pb.state.lock().unwrap().taskbar_seq();
+        assert_eq!(seq.as_deref(), Some("\x1b]9;4;1;100\x07"));
     }
-}
 
-impl Drop for State {
-    fn drop(&mut self) {
-        drop(self.close());
+    #[test]
+    fn refresh_rate() {
+        let pb = AvanceBar::new(100);
+        assert_eq!(pb.interval(), refresh_interval());
+
+        pb.set_refresh_rate(10.0);
+        assert_eq!(pb.interval(), Duration::from_millis(100));
+        pb.set_refresh_rate(60.0);
+        assert_eq!(pb.interval().as_micros(), 16_666);
+        pb.set_refresh_rate(1e6);
+        assert_eq!(pb.interval().as_micros(), 4_166);
+
+        pb.set_refresh_rate(0.0);
+        let prev = pb.progress.prev.load(Ordering::Relaxed);
+        pb.update(1);
+        assert_eq!(pb.progress.prev.load(Ordering::Relaxed), prev);
     }
-}
 
-#[derive(Debug)]
-struct AtomicProgress {
-    begin: Instant,
-    prev: AtomicU64,
-    last: AtomicU64,
-    n: AtomicU64,
-}
+    #[test]
+    fn invisible_bars() {
+        let pb = AvanceBar::new(100);
+        crate::testing::capture(|| assert!(!pb.is_visible()));
 
-impl AtomicProgress {
-    fn new() -> Self {
-        Self {
-            begin: Instant::now(),
-            prev: AtomicU64::new(0),
-            last: AtomicU64::new(0),
-            n: AtomicU64::new(0),
-        }
+        pb.close();
+        assert!(!pb.is_visible());
     }
 
-    fn inc(&self, delta: u64) {
-        self.n.fetch_add(delta, Ordering::AcqRel);
+    #[test]
+    fn multiline() {
+        let pb = AvanceBar::new(100)
+            .with_style(Style::ASCII)
+            .with_desc("task")
+            .with_multiline(true);
+        pb.set_postfix("x=1");
+        pb.update(50);
+        assert_eq!(pb.state.lock().unwrap().height(), 2);
+
+        let lines = pb.render_line(30);
+        let (bar, _) = lines.split_once('\n').unwrap();
+        assert_eq!(bar, "task:  50%|#########0        |");
+
+        let lines = pb.render_line(80);
+        let (_, stats) = lines.split_once('\n').unwrap();
+        assert!(stats.starts_with("50/100 [00:00<00:00, "));
+        assert!(stats.ends_with("it/s, x=1]"));
+
+        // The description gives way to the bar, and the postfix to the statistics
+        pb.set_desc("a".repeat(100));
+        pb.set_postfix("b".repeat(100));
+        let lines = pb.render_line(30);
+        let (bar, stats) = lines.split_once('\n').unwrap();
+        assert_eq!(bar.chars().count(), 30);
+        assert!(bar.ends_with("… 50%|#####0    |"));
+        assert!(stats.chars().count() == 30 || !stats.contains('b'));
+
+        pb.set_multiline(false);
+        assert_eq!(pb.state.lock().unwrap().height(), 1);
+        assert!(!pb.render_line(30).contains('\n'));
     }
 
-    fn ready(&self) -> bool {
-        self.since_last() > INTERVAL
+    #[test]
+    fn long_description() {
+        let desc = "a".repeat(200);
+        let pb = AvanceBar::new(100).with_desc(desc.clone());
+        pb.update(30);
+        assert_eq!(pb.render_line(80).chars().count(), 80);
+
+        let pb = AvanceBar::new(0).with_desc(desc.clone());
+        pb.set_total(None);
+        let line = pb.render_line(80);
+        assert_eq!(line.chars().count(), 80);
+        assert!(line.ends_with("a…"));
+
+        let pb = AvanceBar::new(100).with_desc(desc).with_theme(Theme::Nord);
+        let line = pb.state.lock().unwrap().render(80, true);
+        assert_eq!(crate::format::visible_len(&line), 80);
     }
 
-    fn update(&self) {
-        self.prev
-            .store(self.begin.elapsed().as_nanos() as u64, Ordering::Release);
-        self.last
-            .store(self.n.load(Ordering::Acquire), Ordering::Release);
+    #[test]
+    fn smart_eta_color() {
+        let pb = AvanceBar::new(1000)
+            .with_style(Style::ASCII)
+            .with_smart_eta_color(true);
+        pb.update(100);
+        let mut state = pb.state.lock().unwrap();
+        let colored = |state: &State, color| {
+            let plain = state.render(80, false);
+            let eta = plain.split('<').nth(1).unwrap().split(',').next().unwrap();
+            state
+                .render(80, true)
+                .contains(&format!("<{},", eta.with(color)))
+        };
+
+        // On track, and never colored in plain lines
+        state.record_eta();
+        assert!(!state.render(80, true).contains('\x1b'));
+
+        // Speeding up: the ETA drops by 10s within 1s
+        state.eta = Some((0.0, 1000.0));
+        state.eta_trend = -1.0;
+        state.record_eta();
+        assert!(state.eta_trend < -1.25);
+        assert!(colored(&state, Color::Green));
+        assert!(!state.render(80, false).contains('\x1b'));
+
+        // Slowing down: the ETA rises
+        let elapsed = state.progress.now() as f64 / 1e9;
+        state.eta = Some((elapsed - 1.0, 0.0));
+        state.eta_trend = -1.0;
+        state.record_eta();
+        assert!(state.eta_trend > -0.75);
+        assert!(colored(&state, Color::Red));
+        assert_eq!(crate::format::visible_len(&state.render(80, true)), 80);
     }
 
-    fn since_last(&self) -> u64 {
-        let prev = self.prev.load(Ordering::Acquire);
-        let since_begin = self.begin.elapsed().as_nanos() as u64;
-        since_begin.saturating_sub(prev)
+    #[test]
+    fn reserve_line() {
+        let _globals = lock_globals();
+        let pb = AvanceBar::new(100);
+        let mut out = b"log line".to_vec();
+        LINE_RESERVED.store(false, Ordering::Relaxed);
+        super::reserve_line(&mut out).unwrap();
+        let _ = pb.state.lock().unwrap().draw(Some(0), &mut out);
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("log line\n"));
+        assert!(out.contains("  0%|"));
+
+        // Only once for the bars on screen
+        let mut out = Vec::new();
+        super::reserve_line(&mut out).unwrap();
+        assert!(out.is_empty());
     }
-}
 
-#[derive(Debug, Clone)]
-struct Template {
-    style: Style,
-    width: Option<u16>,
-    desc: Option<Cow<'static, str>>,
-    unit_scale: bool,
-    postfix: Option<Cow<'static, str>>,
-}
+    #[test]
+    fn deferred_increments() {
+        let pb = AvanceBar::new(8 * 10_000).with_refresh_on_inc(true);
+        let frame = pb.state.lock().unwrap().frame.get();
+        std::thread::scope(|t| {
+            for _ in 0..8 {
+                t.spawn(|| {
+                    for _ in 0..10_000 {
+                        pb.inc_deferred();
+                    }
+                });
+            }
+        });
+        assert_eq!(pb.progress.last.load(Ordering::Relaxed), 0);
+        assert_eq!(pb.state.lock().unwrap().frame.get(), frame);
 
-impl Template {
-    fn new() -> Self {
-        Self {
-            style: Default::default(),
-            width: None,
-            desc: None,
-            unit_scale: false,
-            postfix: None,
+        pb.pump();
+        assert_eq!(pb.progress.last.load(Ordering::Relaxed), 8 * 10_000);
+        assert!(pb.render_line(80).contains(" 80000/80000 "));
+    }
+
+    #[test]
+    fn estimated_total() {
+        let pb = AvanceBar::from_total(None)
+            .with_style(Style::ASCII)
+            .with_estimate(100);
+        let pct_of = |pb: &AvanceBar| {
+            let line = pb.render_line(80);
+            line[..line.find('%').unwrap()]
+                .trim()
+                .parse::<u32>()
+                .unwrap()
+        };
+
+        let mut last = pct_of(&pb);
+        assert_eq!(last, 0);
+        for _ in 0..20 {
+            pb.update(50);
+            let pct = pct_of(&pb);
+            assert!(pct >= last);
+            assert!(pct < 100);
+            last = pct;
         }
+        assert_eq!(last, 99);
+        assert!(pb.render_line(80).contains(" 1000/~100 ["));
+
+        // Until the estimate is greatly exceeded
+        pb.update(100_000);
+        assert_eq!(pct_of(&pb), 100);
     }
-}
 
-type AtomicState = Arc<Mutex<State>>;
-type ID = u64;
-type Pos = u16;
+    #[test]
+    fn slow_writer() {
+        // Blocks on its first write until released
+        struct Slow {
+            started: mpsc::Sender<()>,
+            release: Option<mpsc::Receiver<()>>,
+        }
 
-// Minimun update interval (in nanoseconds)
-const INTERVAL: u64 = 100_000_000;
+        impl std::io::Write for Slow {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                if let Some(release) = self.release.take() {
+                    let _ = self.started.send(());
+                    release
+                        .recv_timeout(Duration::from_secs(5))
+                        .map_err(|e| std::io::Error::new(std::io::ErrorKind::TimedOut, e))?;
+                }
+                Ok(buf.len())
+            }
 
-// Next unused ID
-static NEXTID: AtomicU64 = AtomicU64::new(0);
-// How many rows are progress bars allowed to use. If unspecified,
-// use the terminal height.
-static NROWS: AtomicU16 = AtomicU16::new(0);
-// Book-keeping the positions of all bars.
-static POSITIONS: OnceLock<Mutex<HashMap<ID, Pos>>> = OnceLock::new();
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
 
-/// Set how many on-going progress bar can be shown on the screen.
-///
-/// If specified, hides bars outside this limit. If unspecified, adjusts to
-/// the terminal height.
-pub fn set_max_progress_bars(nbars: u16) {
-    let nrows = max(nbars + 1, 2);
-    NROWS.swap(nrows, Ordering::Relaxed);
-}
+        let _globals = lock_globals();
+        let drop_busy = DROP_BUSY.swap(true, Ordering::Relaxed);
+        let pb = AvanceBar::new(100).with_refresh_on_inc(true);
+        let (started, on_started) = mpsc::channel();
+        let (release, on_release) = mpsc::channel();
+        let drawn = std::thread::scope(|t| {
+            let drawer = t.spawn(|| {
+                let mut slow = Slow {
+                    started,
+                    release: Some(on_release),
+                };
+                let mut drawn = Ok(());
+                pb.advance(1, |state| drawn = state.draw(Some(0), &mut slow));
+                drawn
+            });
+            on_started.recv().unwrap();
 
-fn positions() -> &'static Mutex<HashMap<ID, Pos>> {
-    POSITIONS.get_or_init(|| Mutex::new(HashMap::new()))
-}
+            // Not blocked by the drawing, which would time out otherwise
+            for _ in 0..10 {
+                pb.inc();
+            }
+            let _ = release.send(());
+            drawer.join().unwrap()
+        });
+        DROP_BUSY.store(drop_busy, Ordering::Relaxed);
 
-// Whether all progress bars are closed or not
-fn is_finished() -> bool {
-    positions().lock().unwrap().is_empty()
-}
+        assert!(drawn.is_ok());
+        assert_eq!(pb.update_and_get(0), 11);
+        assert!(!pb.progress.drawing.load(Ordering::Relaxed));
+    }
 
-fn terminal_size() -> (u16, u16) {
-    crossterm::terminal::size().unwrap_or((80, 64))
-}
+    #[test]
+    fn dynamic_desc() {
+        let current = Arc::new(AtomicU64::new(0));
+        let file = Arc::clone(&current);
+        let pb = AvanceBar::new(100)
+            .with_dynamic_desc(move || format!("file{}", file.load(Ordering::Relaxed)).into());
 
-fn nrows() -> u16 {
-    let nrows = NROWS.load(Ordering::Relaxed);
+        current.store(7, Ordering::Relaxed);
+        assert!(pb.render_line(80).starts_with("file7:"));
+        current.store(8, Ordering::Relaxed);
+        assert!(pb.render_line(80).starts_with("file8:"));
 
-    if nrows != 0 {
-        min(nrows, terminal_size().1)
-    } else {
-        terminal_size().1
+        pb.set_desc("static");
+        assert!(pb.render_line(80).starts_with("static:"));
     }
-}
 
-fn next_free_pos() -> ID {
-    let mut positions = positions().lock().unwrap();
-    let next_id = NEXTID.fetch_add(1, Ordering::Relaxed);
-    let next_pos = positions.values().max().map(|n| n + 1).unwrap_or(0);
-    positions.insert(next_id, next_pos);
+    #[test]
+    fn nested_bars() {
+        let files = AvanceBar::new(10);
+        for i in 0..10 {
+            let bytes = files.child(100);
+            bytes.update(100);
+            if i % 2 == 0 {
+                bytes.close();
+                bytes.close();
+            }
+        }
+        assert_eq!(files.update_and_get(0), 10);
+        assert!(files.render_line(80).contains(" 10/10 "));
+    }
 
-    next_id
-}
+    #[test]
+    fn clear_postfix() {
+        let pb = AvanceBar::new(100);
+        pb.set_postfix("acc=0.9");
+        assert!(pb.render_line(80).ends_with(", acc=0.9]"));
 
-fn reposition(id: ID) {
-    let mut positions = positions().lock().unwrap();
+        pb.set_postfix("");
+        assert!(pb.render_line(80).ends_with("it/s]"));
 
-    let closed_pos = *positions.get(&id).unwrap();
+        pb.set_postfix("acc=0.9");
+        pb.clear_postfix();
+        assert!(pb.render_line(80).ends_with("it/s]"));
+    }
 
-    positions.remove(&id);
+    #[test]
+    fn postfix_fmt() {
+        // Sets the postfix of the same bar while being formatted
+        struct Nested<'a>(&'a AvanceBar);
 
-    // Move upwards all the bars below the closed bar
-    positions.iter_mut().for_each(|(_, pos)| {
-        if *pos > closed_pos {
-            *pos -= 1;
+        impl std::fmt::Display for Nested<'_> {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                self.0.set_postfix_fmt(format_args!("inner"));
+                f.write_str("outer")
+            }
         }
-    });
-}
 
-#[cfg(test)]
-mod tests {
-    use std::time::Instant;
+        let pb = AvanceBar::new(100);
+        pb.set_postfix_fmt(format_args!("i={}", 1));
+        assert!(pb.render_line(80).ends_with(", i=1]"));
+        pb.set_postfix_fmt(format_args!("{}", Nested(&pb)));
+        assert!(pb.render_line(80).ends_with(", outer]"));
+    }
+}
 
-    use crate::AvanceBar;
+#[cfg(all(test, feature = "disabled"))]
+mod disabled_tests {
+    use crate::{active_bars, AvanceBar};
 
     #[test]
-    fn performance() {
-        let n = 20_000_000;
-
-        let start = Instant::now();
-        for _ in 0..n {}
-        let du = Instant::now().duration_since(start).as_secs_f64();
-        println!("raw: {:.2} it/s", n as f64 / du);
-
-        let pb = AvanceBar::new(n);
-        for _ in pb.with_iter(0..n) {}
+    fn disabled() {
+        let before = active_bars();
+        let pb = AvanceBar::new(100);
+        assert_eq!(active_bars(), before);
+        assert_eq!(pb.inc_and_get(), 0);
+        assert!(pb.position().is_none());
     }
 }
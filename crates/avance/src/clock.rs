@@ -0,0 +1,100 @@
+//! Sources of time, which make the elapsed time, rates and ETAs of progress bars
+//! deterministic in tests
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// A source of the current time for a progress bar, see
+/// [`AvanceBar::with_clock`](crate::AvanceBar::with_clock)
+pub trait Clock: Send + Sync {
+    /// The current time
+    fn now(&self) -> Instant;
+}
+
+/// The monotonic clock of the system, used by progress bars by default
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A clock which only moves when told to, for asserting the exact outputs of
+/// progress bars in tests.
+///
+/// # Examples
+/// ```
+/// use avance::{AvanceBar, MockClock};
+/// use std::sync::Arc;
+/// use std::time::Duration;
+///
+/// let clock = Arc::new(MockClock::new());
+/// let pb = AvanceBar::new(100).with_clock(clock.clone());
+/// clock.advance(Duration::from_secs(42));
+/// assert_eq!(pb.elapsed(), Duration::from_secs(42));
+/// ```
+#[derive(Debug)]
+pub struct MockClock {
+    base: Instant,
+    // Nanoseconds since `base`
+    elapsed: AtomicU64,
+}
+
+impl MockClock {
+    /// Create a clock standing still at the moment
+    pub fn new() -> Self {
+        Self {
+            base: Instant::now(),
+            elapsed: AtomicU64::new(0),
+        }
+    }
+
+    /// Move the clock forward
+    pub fn advance(&self, by: Duration) {
+        self.elapsed
+            .fetch_add(by.as_nanos() as u64, Ordering::Relaxed);
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.base + Duration::from_nanos(self.elapsed.load(Ordering::Relaxed))
+    }
+}
+
+#[cfg(all(test, not(feature = "disabled")))]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::MockClock;
+    use crate::{AvanceBar, Style};
+
+    #[test]
+    fn mock_clock() {
+        let clock = Arc::new(MockClock::new());
+        let pb = AvanceBar::new(100)
+            .with_style(Style::ASCII)
+            .with_clock(clock.clone());
+        assert!(pb.render_line(80).ends_with("| 0/100 [00:00<?, ?it/s]"));
+
+        clock.advance(Duration::from_secs(65));
+        pb.update(50);
+        assert_eq!(pb.elapsed(), Duration::from_secs(65));
+        assert!(pb
+            .render_line(80)
+            .ends_with("| 50/100 [01:05<01:05, 0.77it/s]"));
+
+        // Only the first clock is used
+        let pb = pb.with_clock(Arc::new(MockClock::new()));
+        assert_eq!(pb.elapsed(), Duration::from_secs(65));
+    }
+}
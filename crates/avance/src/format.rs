@@ -1,3 +1,17 @@
+//! Formatting of durations and sizes, the same as shown by progress bars,
+//! which helps keep custom outputs consistent with them
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Format a duration in seconds like `05:42`, or `01:05:42` if it's longer than an hour.
+///
+/// # Examples
+/// ```
+/// use avance::format::format_time;
+///
+/// assert_eq!(format_time(342), "05:42");
+/// assert_eq!(format_time(3942), "01:05:42");
+/// ```
 pub fn format_time(seconds: u64) -> String {
     let m = seconds / 60 % 60;
     let s = seconds % 60;
@@ -7,22 +21,179 @@ pub fn format_time(seconds: u64) -> String {
     }
 }
 
+/// Format a point of time as the time of day like `14:32`, at an offset of
+/// `utc_offset` seconds from UTC.
+///
+/// # Examples
+/// ```
+/// use avance::format::format_clock;
+/// use std::time::{Duration, UNIX_EPOCH};
+///
+/// let time = UNIX_EPOCH + Duration::from_secs(14 * 3600 + 32 * 60);
+/// assert_eq!(format_clock(time, 0), "14:32");
+/// assert_eq!(format_clock(time, -15 * 3600), "23:32");
+/// ```
+pub fn format_clock(time: SystemTime, utc_offset: i32) -> String {
+    let since_epoch = match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_secs() as i64,
+        Err(e) => -(e.duration().as_secs() as i64),
+    };
+    let seconds = (since_epoch + utc_offset as i64).rem_euclid(86400);
+    format!("{:02}:{:02}", seconds / 3600, seconds / 60 % 60)
+}
+
+const UNITS: [&str; 9] = ["", "k", "M", "G", "T", "P", "E", "Z", "Y"];
+const BINARY_UNITS: [&str; 9] = ["", "Ki", "Mi", "Gi", "Ti", "Pi", "Ei", "Zi", "Yi"];
+
+/// Format a number with an SI metric prefix (k = 10^3, M = 10^6, etc.),
+/// keeping three significant digits.
+///
+/// # Examples
+/// ```
+/// use avance::format::format_sizeof;
+///
+/// assert_eq!(format_sizeof(999), "999");
+/// assert_eq!(format_sizeof(1_234), "1.23k");
+/// assert_eq!(format_sizeof(56_780_000), "56.8M");
+/// ```
 pub fn format_sizeof(num: u64) -> String {
+    format_sizeof_with(num, 1000, "")
+}
+
+/// Format a number with a prefix of `base` (like `1.50M` for 1000, or `1.50Mi`
+/// for 1024) followed by a unit suffix, such as `b` for bits or `B` for bytes.
+///
+/// Only bases of 1000 and 1024 are supported, and other bases panic in debug builds.
+///
+/// # Examples
+/// ```
+/// use avance::format::format_sizeof_with;
+///
+/// assert_eq!(format_sizeof_with(1_500_000, 1000, "B"), "1.50MB");
+/// assert_eq!(format_sizeof_with(1_572_864, 1024, "B"), "1.50MiB");
+/// ```
+pub fn format_sizeof_with(num: u64, base: u64, suffix: &str) -> String {
+    debug_assert!(base == 1000 || base == 1024, "unsupported base {}", base);
+    let (unit, precision) = scale_of(num, base);
+    format!("{}{}", format_in(num, unit, precision, base), suffix)
+}
+
+/// Format the progress and the total in the same unit and precision,
+/// which is decided by the total, like [`format_sizeof_with`].
+pub(crate) fn format_sizeof_pair(n: u64, total: u64, base: u64, suffix: &str) -> (String, String) {
+    let (unit, precision) = scale_of(total, base);
+    (
+        format!("{}{}", format_in(n, unit, precision, base), suffix),
+        format!("{}{}", format_in(total, unit, precision, base), suffix),
+    )
+}
+
+/// The prefix (as an index of UNITS) and the precision of a number
+fn scale_of(num: u64, base: u64) -> (usize, usize) {
     let mut num = num as f64;
-    for unit in ["", "k", "M", "G", "T", "P", "E", "Z"] {
+    for unit in 0..UNITS.len() - 1 {
         if num < 999.5 {
-            if num < 99.95 {
-                if num < 9.995 {
-                    return format!("{:.2}{}", num, unit);
+            let precision = match num {
+                num if num < 9.995 => 2,
+                num if num < 99.95 => 1,
+                _ => 0,
+            };
+            return (unit, precision);
+        }
+        num /= base as f64;
+    }
+
+    (UNITS.len() - 1, 1)
+}
+
+fn format_in(num: u64, unit: usize, precision: usize, base: u64) -> String {
+    let num = num as f64 / (base as f64).powi(unit as i32);
+    let prefix = match base {
+        1024 => BINARY_UNITS[unit],
+        _ => UNITS[unit],
+    };
+    format!("{:.*}{}", precision, num, prefix)
+}
+
+/// Shorten a string to at most `max` characters, marking the cut with an ellipsis.
+pub(crate) fn truncate(s: &str, max: usize) -> String {
+    match s.chars().count() {
+        len if len <= max => s.to_string(),
+        _ if max == 0 => String::new(),
+        _ => s.chars().take(max - 1).chain(Some('…')).collect(),
+    }
+}
+
+/// Shorten a string to at most `max` visible characters like [`truncate`], keeping
+/// ANSI escape sequences intact. Colors are reset if the string is cut.
+pub(crate) fn truncate_visible(s: &str, max: usize) -> String {
+    if visible_len(s) <= max {
+        return s.to_string();
+    }
+
+    let mut truncated = String::new();
+    let mut len = 0;
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            truncated.push(c);
+            for c in chars.by_ref() {
+                truncated.push(c);
+                if c.is_ascii_alphabetic() {
+                    break;
                 }
-                return format!("{:.1}{}", num, unit);
             }
-            return format!("{:.0}{}", num, unit);
+        } else if len + 1 < max {
+            truncated.push(c);
+            len += 1;
+        } else {
+            break;
+        }
+    }
+    if max > 0 {
+        truncated.push('…');
+    }
+    if s.contains('\x1b') {
+        truncated.push_str("\x1b[0m");
+    }
+    truncated
+}
+
+/// Number of characters that are visible, skipping ANSI escape sequences.
+pub(crate) fn visible_len(s: &str) -> usize {
+    let mut len = 0;
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // Control Sequence Introducer ends with a letter
+            chars.find(|c| c.is_ascii_alphabetic());
+        } else {
+            len += 1;
         }
-        num /= 1000.0;
     }
+    len
+}
 
-    format!("{:.1}Y", num)
+/// Columns occupied by a character on the terminal. Only east asian wide
+/// characters and emojis are considered to be double-width.
+pub(crate) fn char_width(c: char) -> usize {
+    match c as u32 {
+        0x1100..=0x115F
+        | 0x2E80..=0x303E
+        | 0x3041..=0x33FF
+        | 0x3400..=0x4DBF
+        | 0x4E00..=0x9FFF
+        | 0xA000..=0xA4CF
+        | 0xAC00..=0xD7A3
+        | 0xF900..=0xFAFF
+        | 0xFE30..=0xFE4F
+        | 0xFF00..=0xFF60
+        | 0xFFE0..=0xFFE6
+        | 0x1F300..=0x1F64F
+        | 0x1F900..=0x1F9FF
+        | 0x20000..=0x3FFFD => 2,
+        _ => 1,
+    }
 }
 
 #[cfg(test)]
@@ -43,4 +214,62 @@ mod tests {
         assert_eq!(super::format_sizeof(999_000_000), "999M");
         assert_eq!(super::format_sizeof(999_999_000), "1.00G");
     }
+
+    #[test]
+    fn format_sizeof_with() {
+        assert_eq!(super::format_sizeof_with(1_500_000, 1000, "b"), "1.50Mb");
+        assert_eq!(super::format_sizeof_with(1_572_864, 1024, "B"), "1.50MiB");
+        assert_eq!(super::format_sizeof_with(512, 1024, "B"), "512B");
+        assert_eq!(super::format_sizeof_with(1_234, 1000, "it"), "1.23kit");
+        assert_eq!(super::format_sizeof_with(1_234, 1000, ""), "1.23k");
+    }
+
+    #[test]
+    fn format_sizeof_pair() {
+        let total = 4_000_000;
+        let pair = |n| super::format_sizeof_pair(n, total, 1000, "");
+        assert_eq!(pair(10_000), ("0.01M".to_string(), "4.00M".to_string()));
+        assert_eq!(pair(2_000_000), ("2.00M".to_string(), "4.00M".to_string()));
+        assert_eq!(pair(3_990_000), ("3.99M".to_string(), "4.00M".to_string()));
+        assert_eq!(super::format_sizeof_pair(5, 120, 1000, "").0, "5");
+
+        let bytes = super::format_sizeof_pair(2048, 4096, 1024, "B");
+        assert_eq!(bytes, ("2.00KiB".to_string(), "4.00KiB".to_string()));
+    }
+
+    #[test]
+    fn truncate() {
+        assert_eq!(super::truncate("avance", 10), "avance");
+        assert_eq!(super::truncate("avance", 6), "avance");
+        assert_eq!(super::truncate("avance", 4), "ava…");
+        assert_eq!(super::truncate("avance", 0), "");
+    }
+
+    #[test]
+    fn visible_len() {
+        assert_eq!(super::visible_len("avance"), 6);
+        assert_eq!(super::visible_len("\x1b[38;5;1mava\x1b[0mnce"), 6);
+    }
+
+    #[test]
+    fn truncate_visible() {
+        assert_eq!(super::truncate_visible("avance", 6), "avance");
+        assert_eq!(super::truncate_visible("avance", 4), "ava…");
+        assert_eq!(super::truncate_visible("avance", 0), "");
+        assert_eq!(
+            super::truncate_visible("\x1b[38;5;1mava\x1b[0mnce", 5),
+            "\x1b[38;5;1mava\x1b[0mn…\x1b[0m"
+        );
+        assert_eq!(
+            super::truncate_visible("\x1b[38;5;1mavance\x1b[0m", 3),
+            "\x1b[38;5;1mav…\x1b[0m"
+        );
+    }
+
+    #[test]
+    fn char_width() {
+        assert_eq!(super::char_width('#'), 1);
+        assert_eq!(super::char_width('█'), 1);
+        assert_eq!(super::char_width('中'), 2);
+    }
 }
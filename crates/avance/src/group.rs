@@ -0,0 +1,288 @@
+//! Close a group of progress bars in a deterministic order
+
+use std::sync::Mutex;
+
+use super::*;
+
+/// A group of progress bars, which are closed from top to bottom
+/// when the group is dropped, regardless of how the bars themselves
+/// are dropped.
+///
+/// # Examples
+/// ```
+/// use avance::BarGroup;
+///
+/// let group = BarGroup::new();
+/// std::thread::scope(|t| {
+///     for i in 0..4 {
+///         let pb = group.bar(100).with_desc(format!("task{}", i));
+///         t.spawn(move || pb.with_iter(0..100).for_each(|_| {}));
+///     }
+/// });
+/// // All bars are closed in the rendering order here
+/// drop(group);
+/// ```
+#[derive(Debug, Default)]
+pub struct BarGroup {
+    bars: Mutex<Vec<AvanceBar>>,
+    // Column labels above the bars of a compact group
+    header: Mutex<Option<AvanceBar>>,
+}
+
+impl BarGroup {
+    /// Create an empty group
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create an empty group of [compact](AvanceBar::set_compact) bars, like a
+    /// dashboard, which only shows their descriptions, bars and counts below
+    /// a shared row of column labels:
+    ///
+    /// ```text
+    /// task              progress              done/total
+    /// download:  42%|###########3               | 42/100
+    /// extract:   7%|##0                          | 7/100
+    /// ```
+    ///
+    /// The labels are drawn right away, so the group should be created before
+    /// its bars. Compact bars take one row each, so multiline is ignored for them.
+    ///
+    /// # Examples
+    /// ```
+    /// use avance::BarGroup;
+    ///
+    /// let group = BarGroup::compact();
+    /// let download = group.bar(100).with_desc("download");
+    /// let extract = group.bar(100).with_desc("extract");
+    /// download.update(42);
+    /// extract.update(7);
+    /// ```
+    pub fn compact() -> Self {
+        Self {
+            bars: Mutex::default(),
+            header: Mutex::new(Some(AvanceBar::compact_header())),
+        }
+    }
+
+    /// Create a new progress bar belonging to this group
+    pub fn bar(&self, total: u64) -> AvanceBar {
+        self.add(AvanceBar::new(total))
+    }
+
+    /// Add an existing progress bar to this group
+    pub fn add(&self, pb: AvanceBar) -> AvanceBar {
+        if self.header.lock().unwrap().is_some() {
+            pb.set_compact(true);
+        }
+        self.bars.lock().unwrap().push(pb.clone());
+        pb
+    }
+
+    /// Close all progress bars of this group from top to bottom.
+    /// Bars which are already closed are skipped.
+    pub fn close(&self) {
+        let mut bars = self.bars.lock().unwrap();
+        // The labels are above all bars
+        if let Some(header) = self.header.lock().unwrap().take() {
+            header.close();
+        }
+        for pb in by_position(&bars) {
+            pb.close();
+        }
+        bars.clear();
+    }
+
+    /// Close all progress bars of this group, replacing their lines with a summary
+    /// like `4 tasks done in 00:42 (4000it)`, which counts the items of all bars,
+    /// and the longest elapsed time among them.
+    ///
+    /// Bars which are already closed are not counted.
+    ///
+    /// # Examples
+    /// ```
+    /// use avance::BarGroup;
+    ///
+    /// let group = BarGroup::new();
+    /// std::thread::scope(|t| {
+    ///     for _ in 0..4 {
+    ///         let pb = group.bar(100);
+    ///         t.spawn(move || pb.with_iter(0..100).for_each(|_| {}));
+    ///     }
+    /// });
+    /// group.collapse();
+    /// ```
+    pub fn collapse(&self) {
+        let mut bars = self.bars.lock().unwrap();
+        let open = by_position(&bars);
+        if open.is_empty() {
+            return;
+        }
+
+        let items: u64 = open.iter().map(|pb| pb.update_and_get(0)).sum();
+        let elapsed = open.iter().map(|pb| pb.elapsed()).max().unwrap_or_default();
+        let summary = format!(
+            "{} tasks done in {} ({}it)",
+            open.len(),
+            format::format_time(elapsed.as_secs()),
+            items
+        );
+
+        let header = self.header.lock().unwrap().take();
+        let hidden: Vec<_> = header.iter().chain(open.iter().copied()).collect();
+        bar::discard(&hidden);
+        bar::eprint_above(summary);
+        bars.clear();
+    }
+}
+
+impl Drop for BarGroup {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+/// Run a closure with a [`BarGroup`], and close all bars of the group from top to bottom
+/// when the closure finishes, even if it returns early.
+///
+/// # Examples
+/// ```
+/// fn work() -> Result<(), String> {
+///     avance::scope(|s| {
+///         let pb1 = s.bar(100).with_desc("first");
+///         let pb2 = s.bar(100).with_desc("second");
+///         for i in pb1.with_iter(0..100) {
+///             if i == 50 {
+///                 return Err("failed".to_string());
+///             }
+///         }
+///         pb2.update(100);
+///         Ok(())
+///     })
+/// }
+/// // Both bars are closed in order
+/// assert!(work().is_err());
+/// ```
+pub fn scope<F, R>(f: F) -> R
+where
+    F: FnOnce(&BarGroup) -> R,
+{
+    let group = BarGroup::new();
+    f(&group)
+}
+
+/// Bars that haven't been closed, sorted by their positions
+fn by_position(bars: &[AvanceBar]) -> Vec<&AvanceBar> {
+    let mut open: Vec<_> = bars
+        .iter()
+        .filter_map(|pb| pb.position().map(|pos| (pos, pb)))
+        .collect();
+    open.sort_by_key(|(pos, _)| *pos);
+    open.into_iter().map(|(_, pb)| pb).collect()
+}
+
+#[cfg(all(test, not(feature = "disabled")))]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use super::{scope, BarGroup};
+    use crate::bar::print_plain_lines;
+    use crate::{testing, Style};
+
+    #[test]
+    fn compact_group() {
+        let group = BarGroup::compact();
+        let header = group.header.lock().unwrap().clone().unwrap();
+        let bars: Vec<_> = ["download", "extract", "index"]
+            .iter()
+            .map(|desc| {
+                group
+                    .bar(100)
+                    .with_style(Style::ASCII)
+                    .with_desc(*desc)
+                    .with_multiline(true)
+            })
+            .collect();
+        bars[0].update(42);
+        bars[1].update(7);
+        bars[2].update(100);
+
+        let mut rows = vec![header.render_line(50)];
+        rows.extend(bars.iter().map(|pb| pb.render_line(50)));
+        assert_eq!(
+            rows,
+            [
+                "task              progress              done/total",
+                "download:  42%|###########3               | 42/100",
+                "extract:   7%|##0                          | 7/100",
+                "index: 100%|#############################| 100/100",
+            ]
+        );
+        assert!(header.position() < bars[0].position());
+
+        group.close();
+        assert!(header.position().is_none());
+    }
+
+    #[test]
+    fn close_in_position_order() {
+        let _lines = print_plain_lines();
+        let output = testing::capture(|| {
+            let group = BarGroup::new();
+            let bars: Vec<_> = (0..4)
+                .map(|i| group.bar(100).with_desc(format!("bar{}", i)))
+                .collect();
+
+            thread::scope(|t| {
+                for (i, pb) in bars.iter().enumerate() {
+                    // Bars at the bottom finish first
+                    t.spawn(move || {
+                        thread::sleep(Duration::from_millis(40 - 10 * i as u64));
+                        pb.update(100);
+                    });
+                }
+            });
+
+            drop(group);
+            assert!(bars.iter().all(|pb| pb.position().is_none()));
+        });
+
+        // Bars are closed in the order of being created, rather than finished
+        let order: Vec<_> = output.lines().map(|line| &line[..4]).collect();
+        assert_eq!(order, ["bar0", "bar1", "bar2", "bar3"]);
+    }
+
+    #[test]
+    fn scope_with_early_return() {
+        let mut bars = Vec::new();
+
+        let result: Result<(), ()> = scope(|s| {
+            for _ in 0..3 {
+                bars.push(s.bar(100));
+            }
+            bars[0].update(50);
+            Err(())?;
+            bars[0].update(50);
+            Ok(())
+        });
+
+        assert!(result.is_err());
+        assert!(bars.iter().all(|pb| pb.position().is_none()));
+    }
+
+    #[test]
+    fn collapse_into_summary() {
+        let output = testing::capture(|| {
+            let group = BarGroup::new();
+            for i in 0..4 {
+                group
+                    .bar(100)
+                    .with_desc(format!("collapsed{}", i))
+                    .update(100);
+            }
+            group.collapse();
+        });
+        assert_eq!(output, "4 tasks done in 00:00 (400it)\n");
+    }
+}
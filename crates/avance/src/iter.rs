@@ -1,6 +1,9 @@
 //! A wrapped iterator that shows progress
 
 use std::borrow::Cow;
+use std::cmp::max;
+use std::iter::FusedIterator;
+use std::time::Instant;
 
 use super::*;
 
@@ -8,15 +11,23 @@ use super::*;
 pub struct AvanceIter<Iter> {
     pub(crate) iter: Iter,
     pub(crate) bar: AvanceBar,
+    // Whether the inner iterator has returned None, so that polling it again
+    // doesn't redraw the bar every time
+    pub(crate) exhausted: bool,
 }
 
 /// Wrap an iterator to display its progress
 pub trait AvanceIterator
 where
-    Self: Sized + Iterator,
+    Self: Sized + IntoIterator,
 {
-    /// Wrap an iterator to display its progress, using the upper hound
-    /// of iterator's size as the total length of the progress bar.
+    /// Wrap an iterator (or anything can be turned into an iterator) to display its
+    /// progress, using the upper hound of iterator's size as the total length of the
+    /// progress bar.
+    ///
+    /// The progress bar advances for every item taken from the wrapped iterator, even if
+    /// it's filtered out by later adapters. See [`AvanceIter::filtered`] for only counting
+    /// the items being yielded.
     ///
     /// See another way of progressing with an iterator at [`AvanceBar::with_iter`]
     ///
@@ -27,16 +38,119 @@ where
     /// for _ in (0..1000).avance() {
     ///     // ...
     /// }
+    ///
+    /// for _ in vec![1, 2, 3].avance() {
+    ///     // ...
+    /// }
+    /// ```
+    ///
+    /// Iterators of unknown size, such as a channel's receiver in a worker queue,
+    /// show an unbounded bar, which counts the items until the iterator ends.
+    ///
+    /// ```
+    /// # use avance::AvanceIterator;
+    /// use std::sync::mpsc;
+    ///
+    /// let (tx, rx) = mpsc::channel();
+    /// std::thread::spawn(move || (0..100).for_each(|i| tx.send(i).unwrap()));
+    /// // Ends once all senders are dropped
+    /// for _ in rx.avance() {
+    ///     // ...
+    /// }
+    /// ```
+    ///
+    /// # Wrapping twice
+    ///
+    /// Calling `avance` on an [`AvanceIter`] returns it as is, so it keeps its only bar.
+    /// Generic code can't tell whether an iterator is already wrapped, so libraries
+    /// shouldn't wrap the iterators they're given. Let the caller wrap them, or take
+    /// an [`AvanceBar`] and use [`with_iter`](AvanceBar::with_iter) instead.
+    ///
+    /// ```
+    /// # use avance::AvanceBar;
+    /// fn process(items: impl Iterator<Item = u32>, pb: &AvanceBar) {
+    ///     for _ in pb.with_iter(items) {
+    ///         // ...
+    ///     }
+    /// }
+    ///
+    /// let pb = AvanceBar::new(100);
+    /// process(0..100, &pb);
     /// ```
-    fn avance(self) -> AvanceIter<Self> {
+    fn avance(self) -> AvanceIter<Self::IntoIter> {
+        let iter = self.into_iter();
         AvanceIter {
-            bar: AvanceBar::with_hint(self.size_hint().1),
-            iter: self,
+            bar: AvanceBar::with_hint(iter.size_hint().1),
+            iter,
+            exhausted: false,
         }
     }
+
+    /// Wrap an iterator with a description in one call.
+    ///
+    /// A shortcut for `.avance().with_desc(desc)`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use avance::AvanceIterator;
+    /// for _ in (0..1000).avance_desc("avance") {
+    ///     // ...
+    /// }
+    /// ```
+    fn avance_desc(self, desc: impl Into<Cow<'static, str>>) -> AvanceIter<Self::IntoIter> {
+        self.avance().with_desc(desc)
+    }
+
+    /// Wrap an iterator with a style in one call.
+    ///
+    /// A shortcut for `.avance().with_style(style)`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use avance::{AvanceIterator, Style};
+    /// for _ in (0..1000).avance_styled(Style::Balloon) {
+    ///     // ...
+    /// }
+    /// ```
+    fn avance_styled(self, style: Style) -> AvanceIter<Self::IntoIter> {
+        self.avance().with_style(style)
+    }
 }
 
 impl<Iter: Iterator> AvanceIter<Iter> {
+    /// Return the iterator as is, since it already has a progress bar.
+    ///
+    /// See [`AvanceIterator::avance`]
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use avance::AvanceIterator;
+    /// // Only one bar is shown
+    /// for _ in (0..1000).avance().avance() {
+    ///     // ...
+    /// }
+    /// ```
+    pub fn avance(self) -> Self {
+        self
+    }
+
+    /// Set the description of the progress bar it already has.
+    ///
+    /// See [`AvanceIterator::avance_desc`]
+    pub fn avance_desc(self, desc: impl Into<Cow<'static, str>>) -> Self {
+        self.with_desc(desc)
+    }
+
+    /// Set the style of the progress bar it already has.
+    ///
+    /// See [`AvanceIterator::avance_styled`]
+    pub fn avance_styled(self, style: Style) -> Self {
+        self.with_style(style)
+    }
+
     /// Set the style of a progress bar.
     ///
     /// See [AvanceBar::with_style]
@@ -65,7 +179,7 @@ impl<Iter: Iterator> AvanceIter<Iter> {
     ///     // ...
     /// }
     /// ```
-    pub fn with_style_str(self, s: &'static str) -> Self {
+    pub fn with_style_str(self, s: impl Into<Cow<'static, str>>) -> Self {
         self.bar.set_style_str(s);
         self
     }
@@ -111,11 +225,33 @@ impl<Iter: Iterator> AvanceIter<Iter> {
         self
     }
 
+    /// Set how the progress bar is shown if the iterator yields more items than
+    /// its size hint says.
+    ///
+    /// See [AvanceBar::set_total_overflow]
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use avance::{AvanceIterator, TotalOverflow};
+    /// for _ in (0..1000).avance().with_total_overflow(TotalOverflow::Clamp) {
+    ///     // ...
+    /// }
+    /// ```
+    pub fn with_total_overflow(self, overflow: TotalOverflow) -> Self {
+        self.bar.set_total_overflow(overflow);
+        self
+    }
+
     /// Creates an iterator which gives the original item and a progress bar handler.
     ///
     /// Useful when you use the iterator-style progress bar, and meanwhile want to
     /// control the progress bar when iterating (such as setting the postfix).
     ///
+    /// The total derived from the iterator's size hint can be corrected through the
+    /// handler as well, such as when more work is discovered on the way. An iterator
+    /// of unknown size gives an unbounded bar, which becomes bounded once a total is set.
+    ///
     /// # Examples
     /// ```
     /// # use avance::AvanceIterator;
@@ -124,14 +260,129 @@ impl<Iter: Iterator> AvanceIter<Iter> {
     ///     // ...
     ///     pb.set_postfix("");
     /// }
+    ///
+    /// // The number of pages is only known after fetching the first one
+    /// let mut pages = 0..;
+    /// let pages = std::iter::from_fn(|| pages.next().filter(|&page| page < 5));
+    /// for (page, pb) in pages.avance().with_pb() {
+    ///     if page == 0 {
+    ///         pb.set_total(Some(5));
+    ///     }
+    /// }
     /// ```
     pub fn with_pb(self) -> AvanceBarIter<Iter> {
         AvanceBarIter(self)
     }
+
+    /// Creates an iterator which only yields the items satisfying the predicate,
+    /// and only advances the progress bar for these items.
+    ///
+    /// In contrast, the progress bar advances for every input item when using
+    /// [`Iterator::filter`] after wrapping an iterator.
+    ///
+    /// # Examples
+    /// ```
+    /// # use avance::AvanceIterator;
+    /// // The progress bar advances 500 steps
+    /// for _ in (0..1000).avance().filtered(|x| x % 2 == 0) {
+    ///     // ...
+    /// }
+    /// ```
+    pub fn filtered<P>(self, predicate: P) -> AvanceFilter<Iter, P>
+    where
+        P: FnMut(&Iter::Item) -> bool,
+    {
+        AvanceFilter {
+            iter: self.iter,
+            bar: self.bar,
+            predicate,
+            exhausted: self.exhausted,
+        }
+    }
+
+    /// Show the final progress, which may be skipped by the refresh interval,
+    /// when the inner iterator returns None for the first time
+    fn exhaust(&mut self) {
+        if !self.exhausted {
+            self.exhausted = true;
+            self.bar.redraw();
+        }
+    }
 }
 
-// Implement AcanceIterator trait for all Iterator types
-impl<Iter: Iterator> AvanceIterator for Iter {}
+impl<Iter, T, E> AvanceIter<Iter>
+where
+    Iter: Iterator<Item = Result<T, E>>,
+{
+    /// Creates an iterator which yields all items, but only advances the
+    /// progress bar for `Ok` items.
+    ///
+    /// # Examples
+    /// ```
+    /// # use avance::AvanceIterator;
+    /// use std::io::{BufRead, Cursor};
+    ///
+    /// let reader = Cursor::new("line1\nline2\n");
+    /// for line in reader.lines().avance().on_ok() {
+    ///     // ...
+    /// }
+    /// ```
+    pub fn on_ok(self) -> AvanceOk<Iter> {
+        AvanceOk(self)
+    }
+}
+
+/// Wrap an iterator to display its progress, a shortcut for [`AvanceIterator::avance`]
+/// which doesn't need the trait in scope.
+///
+/// Options of the progress bar can follow the iterator as `key = value` pairs,
+/// where the keys are `desc`, `style`, `style_str`, `width`, `unit_scale`
+/// and `total_overflow`, each of which calls the `with_*` method of the same name.
+///
+/// # Examples
+///
+/// ```
+/// use avance::{progress, Style};
+///
+/// for _ in progress!(0..100) {
+///     // ...
+/// }
+///
+/// for _ in progress!(0..100, desc = "task", style = Style::Block) {
+///     // ...
+/// }
+/// ```
+#[macro_export]
+macro_rules! progress {
+    (@with $iter:expr $(,)?) => { $iter };
+    (@with $iter:expr, desc = $value:expr $(, $($rest:tt)*)?) => {
+        $crate::progress!(@with $iter.with_desc($value) $(, $($rest)*)?)
+    };
+    (@with $iter:expr, style = $value:expr $(, $($rest:tt)*)?) => {
+        $crate::progress!(@with $iter.with_style($value) $(, $($rest)*)?)
+    };
+    (@with $iter:expr, style_str = $value:expr $(, $($rest:tt)*)?) => {
+        $crate::progress!(@with $iter.with_style_str($value) $(, $($rest)*)?)
+    };
+    (@with $iter:expr, width = $value:expr $(, $($rest:tt)*)?) => {
+        $crate::progress!(@with $iter.with_width($value) $(, $($rest)*)?)
+    };
+    (@with $iter:expr, unit_scale = $value:expr $(, $($rest:tt)*)?) => {
+        $crate::progress!(@with $iter.with_unit_scale($value) $(, $($rest)*)?)
+    };
+    (@with $iter:expr, total_overflow = $value:expr $(, $($rest:tt)*)?) => {
+        $crate::progress!(@with $iter.with_total_overflow($value) $(, $($rest)*)?)
+    };
+    (@with $iter:expr, $key:ident = $($rest:tt)*) => {
+        compile_error!(concat!("unknown option of progress!: ", stringify!($key)))
+    };
+    ($iter:expr $(, $($options:tt)*)?) => {
+        $crate::progress!(@with $crate::AvanceIterator::avance($iter) $(, $($options)*)?)
+    };
+}
+
+// Implement AcanceIterator trait for all IntoIterator types
+impl<Iter: IntoIterator> AvanceIterator for Iter {}
 
 impl<Iter: Iterator> Iterator for AvanceIter<Iter> {
     type Item = Iter::Item;
@@ -141,6 +392,7 @@ impl<Iter: Iterator> Iterator for AvanceIter<Iter> {
             self.bar.inc();
             Some(next)
         } else {
+            self.exhaust();
             None
         }
     }
@@ -148,6 +400,100 @@ impl<Iter: Iterator> Iterator for AvanceIter<Iter> {
     fn size_hint(&self) -> (usize, Option<usize>) {
         self.iter.size_hint()
     }
+
+    fn count(self) -> usize {
+        let n = self.iter.count();
+        self.bar.update(n as u64);
+        self.bar.redraw();
+        n
+    }
+
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        let (next, skipped) = match self.iter.size_hint() {
+            // The inner iterator knows how many items it will skip, and may
+            // skip them without visiting each one
+            (lo, Some(hi)) if lo == hi => {
+                let next = self.iter.nth(n);
+                (next, lo.min(n.saturating_add(1)))
+            }
+            _ => {
+                let skipped = self.iter.by_ref().take(n).count();
+                let next = self.iter.next();
+                let skipped = skipped + next.is_some() as usize;
+                (next, skipped)
+            }
+        };
+        self.bar.update(skipped as u64);
+        if next.is_none() {
+            self.exhaust();
+        }
+        next
+    }
+
+    fn fold<B, F>(self, init: B, mut f: F) -> B
+    where
+        F: FnMut(B, Self::Item) -> B,
+    {
+        let mut batch = Batch::new(&self.bar);
+        let acc = self.iter.fold(init, |acc, item| {
+            batch.tick();
+            f(acc, item)
+        });
+        batch.flush();
+        self.bar.redraw();
+        acc
+    }
+
+    fn for_each<F>(self, mut f: F)
+    where
+        F: FnMut(Self::Item),
+    {
+        self.fold((), |(), item| f(item));
+    }
+}
+
+/// Counts items locally, and advances a progress bar in batches, whose size
+/// adapts to take about a refresh interval
+struct Batch<'a> {
+    bar: &'a AvanceBar,
+    pending: u64,
+    size: u64,
+    last: Instant,
+}
+
+impl<'a> Batch<'a> {
+    fn new(bar: &'a AvanceBar) -> Self {
+        Self {
+            bar,
+            pending: 0,
+            size: 1,
+            last: Instant::now(),
+        }
+    }
+
+    fn tick(&mut self) {
+        self.pending += 1;
+        if self.pending < self.size {
+            return;
+        }
+        self.flush();
+
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last);
+        self.last = now;
+        if elapsed < self.bar.interval() / 4 {
+            self.size = self.size.saturating_mul(2);
+        } else if elapsed > self.bar.interval() {
+            self.size = max(self.size / 2, 1);
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.pending > 0 {
+            self.bar.update(self.pending);
+            self.pending = 0;
+        }
+    }
 }
 
 impl<Iter: ExactSizeIterator> ExactSizeIterator for AvanceIter<Iter> {
@@ -156,12 +502,15 @@ impl<Iter: ExactSizeIterator> ExactSizeIterator for AvanceIter<Iter> {
     }
 }
 
+impl<Iter: FusedIterator> FusedIterator for AvanceIter<Iter> {}
+
 impl<Iter: DoubleEndedIterator> DoubleEndedIterator for AvanceIter<Iter> {
     fn next_back(&mut self) -> Option<Self::Item> {
         if let Some(next) = self.iter.next_back() {
             self.bar.inc();
             Some(next)
         } else {
+            self.exhaust();
             None
         }
     }
@@ -183,8 +532,18 @@ impl<Iter: Iterator> Iterator for AvanceBarIter<Iter> {
     fn size_hint(&self) -> (usize, Option<usize>) {
         self.0.size_hint()
     }
+
+    fn count(self) -> usize {
+        self.0.count()
+    }
+
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        self.0.nth(n).map(|item| (item, self.0.bar.clone()))
+    }
 }
 
+impl<Iter: FusedIterator> FusedIterator for AvanceBarIter<Iter> {}
+
 impl<Iter: ExactSizeIterator> ExactSizeIterator for AvanceBarIter<Iter> {
     fn len(&self) -> usize {
         self.0.len()
@@ -196,3 +555,366 @@ impl<Iter: DoubleEndedIterator> DoubleEndedIterator for AvanceBarIter<Iter> {
         self.0.next_back().map(|item| (item, self.0.bar.clone()))
     }
 }
+
+/// An iterator that only counts the items satisfying a predicate.
+///
+/// See [`AvanceIter::filtered`]
+pub struct AvanceFilter<Iter, P> {
+    iter: Iter,
+    bar: AvanceBar,
+    predicate: P,
+    exhausted: bool,
+}
+
+impl<Iter, P> Iterator for AvanceFilter<Iter, P>
+where
+    Iter: Iterator,
+    P: FnMut(&Iter::Item) -> bool,
+{
+    type Item = Iter::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let next = self.iter.find(&mut self.predicate);
+        match next {
+            Some(_) => self.bar.inc(),
+            None if !self.exhausted => {
+                self.exhausted = true;
+                self.bar.redraw();
+            }
+            None => {}
+        }
+        next
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, self.iter.size_hint().1)
+    }
+}
+
+impl<Iter, P> FusedIterator for AvanceFilter<Iter, P>
+where
+    Iter: FusedIterator,
+    P: FnMut(&Iter::Item) -> bool,
+{
+}
+
+/// An iterator that only counts `Ok` items.
+///
+/// See [`AvanceIter::on_ok`]
+pub struct AvanceOk<Iter>(AvanceIter<Iter>);
+
+impl<Iter, T, E> Iterator for AvanceOk<Iter>
+where
+    Iter: Iterator<Item = Result<T, E>>,
+{
+    type Item = Result<T, E>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let next = self.0.iter.next();
+        match next {
+            Some(Ok(_)) => self.0.bar.inc(),
+            Some(Err(_)) => {}
+            None => self.0.exhaust(),
+        }
+        next
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<Iter, T, E> FusedIterator for AvanceOk<Iter> where Iter: FusedIterator<Item = Result<T, E>> {}
+
+#[cfg(all(test, not(feature = "disabled")))]
+mod tests {
+    use std::time::Instant;
+
+    use std::sync::{Arc, Mutex};
+
+    use crate::bar::lock_globals;
+    use crate::observer::{clear_observer, set_observer, Event};
+    use crate::{AvanceBar, AvanceIterator, Style, TotalOverflow};
+
+    #[test]
+    fn into_iterator() {
+        let v = vec![1, 2, 3];
+        assert_eq!(v.iter().avance().len(), 3);
+        assert_eq!((&v).avance().sum::<i32>(), 6);
+        assert_eq!([1, 2, 3, 4].avance().count(), 4);
+        assert_eq!(v.avance().collect::<Vec<_>>(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn fluent_shortcuts() {
+        let iter = (0..10).avance_desc("shortcut");
+        assert!(iter.bar.render_line(80).starts_with("shortcut: "));
+
+        let iter = (0..10).avance_styled(Style::Block).with_desc("styled");
+        assert!(iter.bar.render_line(80).starts_with("styled: "));
+        assert_eq!(iter.sum::<i32>(), 45);
+    }
+
+    /// An iterator whose size hint under-reports
+    struct Liar(std::ops::Range<u64>);
+
+    impl Iterator for Liar {
+        type Item = u64;
+
+        fn next(&mut self) -> Option<u64> {
+            self.0.next()
+        }
+
+        fn size_hint(&self) -> (usize, Option<usize>) {
+            (0, Some(100))
+        }
+    }
+
+    #[test]
+    fn lying_size_hint() {
+        let iter = Liar(0..150).avance();
+        let pb = iter.bar.clone();
+        iter.for_each(|_| {});
+        assert!(pb.render_line(80).starts_with("100%|"));
+        assert!(pb.render_line(80).contains(" 150/100 "));
+
+        let iter = Liar(0..150)
+            .avance()
+            .with_total_overflow(TotalOverflow::Promote);
+        let pb = iter.bar.clone();
+        iter.for_each(|_| {});
+        assert!(pb.render_line(80).contains(" 150/150 "));
+
+        let iter = Liar(0..150)
+            .avance()
+            .with_total_overflow(TotalOverflow::Clamp);
+        let pb = iter.bar.clone();
+        iter.for_each(|_| {});
+        assert!(pb.render_line(80).contains(" 100/100 "));
+
+        let iter = Liar(0..150)
+            .avance()
+            .with_total_overflow(TotalOverflow::Unbounded);
+        let pb = iter.bar.clone();
+        iter.for_each(|_| {});
+        assert!(pb.render_line(80).starts_with("150it "));
+        assert_eq!(pb.total(), Some(100));
+
+        // Not affected until the total is exceeded
+        let iter = Liar(0..50).avance();
+        let pb = iter.bar.clone();
+        iter.for_each(|_| {});
+        assert!(pb.render_line(80).contains(" 50/100 "));
+    }
+
+    #[test]
+    fn progress_macro() {
+        assert_eq!(crate::progress!(0..10).sum::<i32>(), 45);
+
+        let iter = crate::progress!(0..10, desc = "macro", style = Style::Block, width = 60,);
+        assert!(iter.bar.render_line(80).starts_with("macro: "));
+        assert_eq!(iter.bar.render_line(80).chars().count(), 60);
+    }
+
+    #[test]
+    fn channel_receiver() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        for i in 0..10 {
+            tx.send(i).unwrap();
+        }
+
+        let iter = (&rx).avance();
+        let pb = iter.bar.clone();
+        assert_eq!(pb.total(), None);
+        assert_eq!(iter.take(4).count(), 4);
+        assert!(pb.render_line(80).starts_with("4it "));
+
+        drop(tx);
+        let iter = rx.avance();
+        let pb = iter.bar.clone();
+        assert_eq!(iter.sum::<i32>(), 39);
+        assert!(pb.render_line(80).starts_with("6it "));
+    }
+
+    #[test]
+    fn with_iter_derives_total() {
+        let v = vec![0; 50];
+
+        let pb = AvanceBar::new(100);
+        pb.with_iter(&v).for_each(|_| {});
+        assert!(pb.render_line(80).starts_with(" 50%"));
+
+        let pb = AvanceBar::new(100);
+        pb.set_total(None);
+        pb.with_iter(v).for_each(|_| {});
+        assert!(pb.render_line(80).starts_with("100%"));
+
+        let pb = AvanceBar::new(4);
+        pb.with_iter([1, 2]).for_each(|_| {});
+        assert!(pb.render_line(80).starts_with(" 50%"));
+    }
+
+    #[test]
+    fn set_total_with_pb() {
+        let mut pages = 0..;
+        let pages = std::iter::from_fn(|| pages.next().filter(|&page| page < 10));
+        let iter = pages.avance().with_pb();
+        let pb = iter.0.bar.clone();
+        assert!(pb.render_line(80).starts_with("0it "));
+
+        for (page, pb) in iter {
+            if page == 0 {
+                pb.set_total(Some(10));
+            }
+        }
+        assert!(pb.render_line(80).starts_with("100%|"));
+        assert!(pb.render_line(80).contains(" 10/10 "));
+
+        // More work is discovered on the way
+        let iter = (0..5).avance().with_pb();
+        let pb = iter.0.bar.clone();
+        for (i, pb) in iter {
+            if i == 2 {
+                pb.set_total(Some(8));
+            }
+        }
+        assert!(pb.render_line(80).contains(" 5/8 "));
+    }
+
+    #[test]
+    #[ignore = "benchmark"]
+    fn fold_in_batches() {
+        let n = 20_000_000;
+
+        let iter = (0..n).avance();
+        let pb = iter.bar.clone();
+        let start = Instant::now();
+        let mut sum = 0;
+        for i in iter {
+            sum += i;
+        }
+        let du = Instant::now().duration_since(start).as_secs_f64();
+        println!("next: {:.2} it/s", n as f64 / du);
+        assert_eq!(pb.update_and_get(0), n);
+
+        let iter = (0..n).avance();
+        let pb = iter.bar.clone();
+        let start = Instant::now();
+        assert_eq!(iter.sum::<u64>(), sum);
+        let du = Instant::now().duration_since(start).as_secs_f64();
+        println!("fold: {:.2} it/s", n as f64 / du);
+        assert_eq!(pb.update_and_get(0), n);
+    }
+
+    #[test]
+    fn no_double_wrapping() {
+        let iter = (0..100).avance();
+        let id = iter.bar.id();
+        let iter = iter
+            .avance()
+            .avance_desc("once")
+            .avance_styled(Style::Block);
+        assert_eq!(iter.bar.id(), id);
+
+        let pb = AvanceBar::new(100);
+        assert_eq!(pb.with_iter(0..100).avance().bar.id(), pb.id());
+    }
+
+    #[test]
+    fn nth_and_count() {
+        let mut iter = (0..100).avance();
+        let pb = iter.bar.clone();
+        assert_eq!(iter.nth(5), Some(5));
+        assert_eq!(pb.update_and_get(0), 6);
+        assert_eq!(iter.nth(200), None);
+        assert_eq!(pb.update_and_get(0), 100);
+
+        // Without an exact size, the skipped items are counted one by one
+        let mut iter = (0..100).filter(|x| x % 2 == 0).avance();
+        let pb = iter.bar.clone();
+        assert_eq!(iter.nth(5), Some(10));
+        assert_eq!(pb.update_and_get(0), 6);
+        assert_eq!(iter.nth(100), None);
+        assert_eq!(pb.update_and_get(0), 50);
+
+        let mut iter = (0..100).avance().with_pb();
+        assert_eq!(
+            iter.nth(5).map(|(i, pb)| (i, pb.update_and_get(0))),
+            Some((5, 6))
+        );
+        let iter = (0..100).avance();
+        let pb = iter.bar.clone();
+        assert_eq!(iter.count(), 100);
+        assert_eq!(pb.update_and_get(0), 100);
+    }
+
+    #[test]
+    fn fused() {
+        fn is_fused<I: std::iter::FusedIterator>(_: &I) {}
+        let iter = (0..10).avance();
+        is_fused(&iter);
+        is_fused(&iter.with_pb());
+        is_fused(&(0..10).avance().filtered(|x| x % 2 == 0));
+        is_fused(&vec![Ok::<_, ()>(1)].avance().on_ok());
+    }
+
+    #[test]
+    fn redraw_once_exhausted() {
+        // The observer is global
+        let _globals = lock_globals();
+        let redrawn = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&redrawn);
+        set_observer(move |event| {
+            if let Event::Redrawn(id, _) = event {
+                sink.lock().unwrap().push(id);
+            }
+        });
+
+        let mut iter = (0..3).avance();
+        let mut filtered = (0..3).avance().filtered(|x| x % 2 == 0);
+        let mut oks = vec![Ok::<_, ()>(1)].avance().on_ok();
+        for _ in 0..5 {
+            iter.next();
+            filtered.next();
+            oks.next();
+        }
+        clear_observer();
+
+        let redrawn = redrawn.lock().unwrap();
+        assert_eq!(redrawn.iter().filter(|&&id| id == iter.bar.id()).count(), 1);
+        assert_eq!(
+            redrawn
+                .iter()
+                .filter(|&&id| id == filtered.bar.id())
+                .count(),
+            1
+        );
+        assert_eq!(
+            redrawn.iter().filter(|&&id| id == oks.0.bar.id()).count(),
+            1
+        );
+    }
+
+    #[test]
+    fn count_inputs_or_yields() {
+        let inputs = (0..100).avance();
+        let pb = inputs.bar.clone();
+        assert_eq!(inputs.filter(|x| x % 4 == 0).count(), 25);
+        assert_eq!(pb.update_and_get(0), 100);
+
+        let yields = (0..100).avance().filtered(|x| x % 4 == 0);
+        let pb = yields.bar.clone();
+        assert_eq!(yields.count(), 25);
+        assert_eq!(pb.update_and_get(0), 25);
+    }
+
+    #[test]
+    fn count_ok_only() {
+        let items = vec![Ok(1), Err("bad"), Ok(2), Ok(3), Err("bad")];
+        let iter = items.avance().on_ok();
+        let pb = iter.0.bar.clone();
+
+        assert_eq!(iter.count(), 5);
+        assert_eq!(pb.update_and_get(0), 3);
+    }
+}
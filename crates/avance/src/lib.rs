@@ -55,6 +55,8 @@
 //!   - configuration changes (such as changing its style or width)
 //! - If a progress bar's width is too large, environment width will be used instead.
 //! - A progress bar can be **shared among threads fearlessly**.
+//! - Printing to the terminal while bars are in progress should go through
+//!   [`println`], otherwise the bars may get scattered.
 //!
 //! # Iterator
 //!
@@ -72,6 +74,11 @@
 //!     // ...
 //!     pb.set_postfix("");
 //! }
+//!
+//! // Or even shorter, without importing the trait
+//! for _ in avance::progress!(0..100, desc = "quick") {
+//!     // ...
+//! }
 //! ```
 //!
 //! # Style
@@ -86,18 +93,46 @@
 //! }
 //! ```
 //!
+//! # Features
+//!
+//! * `disabled`: Turn progress bars into no-ops, which never track progress nor draw
+//!   anything, while keeping the API unchanged. Useful for binaries which don't want
+//!   the overhead of progress bars used by their dependencies.
+//!
 //! # TODOs:
 //! - [ ] A monitor for very slow progress bars
 //! - [ ] A convenient template for customizing progress bars
 
 pub mod bar;
-pub(crate) mod format;
+pub mod clock;
+pub mod format;
+pub mod group;
 pub mod iter;
+pub mod observer;
+pub mod pipeline;
+pub mod source;
 pub mod style;
+pub mod testing;
 
 #[doc(inline)]
-pub use bar::{set_max_progress_bars, AvanceBar};
+pub use bar::{
+    active_bars, finalize, max_progress_bars, println, refresh_interval, render_all,
+    set_default_style, set_default_terminal_size, set_default_width, set_drop_busy_draws,
+    set_dumb_step, set_manage_cursor, set_max_progress_bars, set_reserve_line,
+    set_show_hidden_indicator, set_sticky, set_utc_offset, AvanceBar, BarOptions, Total,
+    TotalOverflow, DEFAULT_TERMINAL_SIZE,
+};
+#[doc(inline)]
+pub use clock::{Clock, MockClock, SystemClock};
+#[doc(inline)]
+pub use group::{scope, BarGroup};
+#[doc(inline)]
+pub use iter::{AvanceBarIter, AvanceFilter, AvanceIter, AvanceIterator, AvanceOk};
+#[doc(inline)]
+pub use observer::{clear_observer, set_observer, Event, Snapshot};
+#[doc(inline)]
+pub use pipeline::LinkedBars;
 #[doc(inline)]
-pub use iter::{AvanceBarIter, AvanceIter, AvanceIterator};
+pub use source::{track, ProgressSource, Tracked};
 #[doc(inline)]
-pub use style::Style;
+pub use style::{detect_style, PercentPosition, Style, StyleError, Theme};
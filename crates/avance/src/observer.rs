@@ -0,0 +1,142 @@
+//! Observe progress bars, such as for forwarding their progress to metrics or a GUI
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Something happened to a progress bar, which is identified by
+/// [`AvanceBar::id`](crate::AvanceBar::id)
+#[derive(Debug, Clone)]
+pub enum Event {
+    /// A bar is created
+    Created(u64),
+
+    /// A bar is redrawn on an update, which happens at most once per refresh interval
+    Redrawn(u64, Snapshot),
+
+    /// A bar is closed
+    Closed(u64, Snapshot),
+}
+
+/// The progress of a bar at some moment
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct Snapshot {
+    /// Steps made so far
+    pub n: u64,
+
+    /// Total length, or None if it's unbounded
+    pub total: Option<u64>,
+
+    /// Time since the bar started
+    pub elapsed: Duration,
+
+    /// Iterations per second, as shown by the bar, or NaN if no time has elapsed
+    pub rate: f64,
+
+    /// The description, if any
+    pub desc: Option<String>,
+}
+
+type Observer = Arc<dyn Fn(Event) + Send + Sync>;
+
+// Whether an observer is set, so that no snapshot is taken otherwise
+static OBSERVED: AtomicBool = AtomicBool::new(false);
+static OBSERVER: Mutex<Option<Observer>> = Mutex::new(None);
+
+/// Set a global observer, which is notified when any progress bar is created,
+/// redrawn on an update, or closed. It replaces the previous observer, if any.
+///
+/// The observer is called from the threads updating the bars, without any bar
+/// being locked, so it may use progress bars as well. Keep it fast, as it runs
+/// on every redraw.
+///
+/// # Examples
+/// ```
+/// use avance::observer::{set_observer, Event};
+/// use avance::AvanceBar;
+///
+/// set_observer(|event| {
+///     if let Event::Closed(id, snapshot) = event {
+///         // e.g. export to a metrics system
+///         let _ = (id, snapshot.n);
+///     }
+/// });
+/// let pb = AvanceBar::new(100);
+/// ```
+pub fn set_observer<F>(observer: F)
+where
+    F: Fn(Event) + Send + Sync + 'static,
+{
+    *OBSERVER.lock().unwrap() = Some(Arc::new(observer));
+    OBSERVED.store(true, Ordering::Release);
+}
+
+/// Remove the global observer, see [`set_observer`]
+pub fn clear_observer() {
+    OBSERVED.store(false, Ordering::Release);
+    *OBSERVER.lock().unwrap() = None;
+}
+
+/// Whether events should be generated at all
+pub(crate) fn is_observed() -> bool {
+    OBSERVED.load(Ordering::Acquire)
+}
+
+/// Pass an event to the observer. No state should be locked by the caller.
+pub(crate) fn notify(event: Event) {
+    // The observer is called without holding the lock, so it can replace itself
+    let observer = OBSERVER.lock().unwrap().clone();
+    if let Some(observer) = observer {
+        observer(event);
+    }
+}
+
+#[cfg(all(test, not(feature = "disabled")))]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::{clear_observer, set_observer, Event};
+    use crate::bar::lock_globals;
+    use crate::AvanceBar;
+
+    #[test]
+    fn count_events() {
+        // The observer is global
+        let _globals = lock_globals();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&events);
+        set_observer(move |event| sink.lock().unwrap().push(event));
+
+        let pb = AvanceBar::new(3)
+            .with_refresh_on_inc(true)
+            .with_desc("task");
+        let id = pb.id();
+        for _ in 0..3 {
+            pb.inc();
+        }
+        pb.close();
+        pb.close();
+        clear_observer();
+        pb.inc();
+
+        // Other tests may create bars at the same time
+        let events: Vec<_> = events
+            .lock()
+            .unwrap()
+            .drain(..)
+            .filter(|event| match event {
+                Event::Created(i) | Event::Redrawn(i, _) | Event::Closed(i, _) => *i == id,
+            })
+            .collect();
+        assert_eq!(events.len(), 5);
+        assert!(matches!(events[0], Event::Created(_)));
+        match &events[4] {
+            Event::Closed(_, snapshot) => {
+                assert_eq!((snapshot.n, snapshot.total), (3, Some(3)));
+                assert_eq!(snapshot.desc.as_deref(), Some("task"));
+            }
+            event => panic!("unexpected event: {:?}", event),
+        }
+    }
+}
@@ -0,0 +1,126 @@
+//! Views derived from the bars of a producer/consumer pipeline
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use super::*;
+
+/// A bar showing the backlog of a pipeline, which is the number of items produced
+/// but not consumed yet, along with the net rate at which items are consumed:
+///
+/// ```text
+/// backlog:  40%|####      | 4/10 [00:01<00:01, net 12.00it/s]
+/// ```
+///
+/// It's derived from the bars of the producer and the consumer, which are
+/// advanced as usual, and is updated by [`refresh`](Self::refresh).
+///
+/// # Examples
+/// ```
+/// use avance::{AvanceBar, LinkedBars};
+/// use std::sync::mpsc;
+///
+/// let (tx, rx) = mpsc::sync_channel(8);
+/// let producer = AvanceBar::new(100).with_desc("produced");
+/// let consumer = AvanceBar::new(100).with_desc("consumed");
+/// let backlog = LinkedBars::new(&producer, &consumer, 8);
+///
+/// std::thread::spawn(move || {
+///     for i in producer.with_iter(0..100) {
+///         tx.send(i).unwrap();
+///     }
+/// });
+/// for _ in consumer.with_iter(rx) {
+///     backlog.refresh();
+/// }
+/// ```
+#[derive(Debug)]
+pub struct LinkedBars {
+    producer: AvanceBar,
+    consumer: AvanceBar,
+    backlog: AvanceBar,
+    count: Arc<AtomicU64>,
+    // When the net rate was last shown
+    shown: Mutex<Option<Instant>>,
+}
+
+impl LinkedBars {
+    /// Create a bar for the backlog between a producer and a consumer, whose
+    /// total is the capacity of the buffer between them, such as a bounded channel.
+    pub fn new(producer: &AvanceBar, consumer: &AvanceBar, capacity: u64) -> Self {
+        let count = Arc::new(AtomicU64::new(0));
+        let backlog = AvanceBar::tracking(capacity, Arc::clone(&count))
+            .with_desc("backlog")
+            // The rate of the backlog itself means little
+            .with_show_rate(false);
+
+        let linked = Self {
+            producer: producer.clone(),
+            consumer: consumer.clone(),
+            backlog,
+            count,
+            shown: Mutex::new(None),
+        };
+        linked.refresh();
+        linked
+    }
+
+    /// Read the counts of both bars, and redraw the backlog if it's time to refresh.
+    /// Returns the backlog, which is the number of items produced but not consumed.
+    pub fn refresh(&self) -> u64 {
+        let produced = self.producer.update_and_get(0);
+        let consumed = self.consumer.snapshot();
+        let backlog = produced.saturating_sub(consumed.n);
+        self.count.store(backlog, Ordering::Release);
+        self.backlog.update(0);
+
+        // The net rate changes on every item, so it's only updated once per refresh interval
+        let mut shown = self.shown.lock().unwrap();
+        if shown.map_or(true, |shown| shown.elapsed() >= self.backlog.interval()) {
+            *shown = Some(Instant::now());
+            drop(shown);
+            match consumed.rate.is_finite() {
+                true => self
+                    .backlog
+                    .set_postfix_fmt(format_args!("net {:.02}it/s", consumed.rate)),
+                false => self.backlog.set_postfix("net ?it/s"),
+            }
+        }
+        backlog
+    }
+
+    /// The bar showing the backlog, which can be configured like any other bar.
+    pub fn bar(&self) -> &AvanceBar {
+        &self.backlog
+    }
+}
+
+#[cfg(all(test, not(feature = "disabled")))]
+mod tests {
+    use super::LinkedBars;
+    use crate::{AvanceBar, Style};
+
+    #[test]
+    fn backlog() {
+        let producer = AvanceBar::new(100);
+        let consumer = AvanceBar::new(100);
+        let linked = LinkedBars::new(&producer, &consumer, 10);
+        linked.bar().set_style(Style::ASCII);
+        assert_eq!(linked.refresh(), 0);
+
+        producer.update(10);
+        consumer.update(4);
+        assert_eq!(linked.refresh(), 6);
+        let line = linked.bar().render_line(80);
+        assert!(line.starts_with("backlog:  60%|######"));
+        assert!(line.contains("| 6/10 ["));
+        assert!(line.contains(", net "));
+
+        consumer.update(6);
+        assert_eq!(linked.refresh(), 0);
+        // The consumer never gets ahead
+        consumer.update(1);
+        assert_eq!(linked.refresh(), 0);
+    }
+}
@@ -0,0 +1,150 @@
+//! Track the progress of anything that knows how much work is left
+
+use std::ops::Deref;
+
+use super::*;
+
+/// A source of work which can tell how many items are left, such as a
+/// paginated API or a stream with a known remaining count.
+///
+/// Unlike [`AvanceIterator`], a source doesn't need to be an iterator.
+/// See [`track`] for displaying its progress.
+pub trait ProgressSource {
+    /// Number of items left, or None if it's unknown for now
+    fn remaining(&self) -> Option<u64>;
+}
+
+/// Wrap a source of work to display its progress.
+///
+/// The total of the progress bar is what the source has left when it's wrapped,
+/// and the bar advances as the remaining count decreases. If the remaining count
+/// is unknown at first, the bar stays unbounded until the count becomes known.
+///
+/// # Examples
+/// ```
+/// use avance::{track, ProgressSource};
+///
+/// struct Queue(Vec<u32>);
+///
+/// impl ProgressSource for Queue {
+///     fn remaining(&self) -> Option<u64> {
+///         Some(self.0.len() as u64)
+///     }
+/// }
+///
+/// let mut queue = track(Queue(vec![1, 2, 3]));
+/// while let Some(_) = queue.with_mut(|q| q.0.pop()) {
+///     // ...
+/// }
+/// ```
+pub fn track<S: ProgressSource>(source: S) -> Tracked<S> {
+    let bar = AvanceBar::from_total(source.remaining());
+    bar.refresh();
+    Tracked { source, bar }
+}
+
+/// A source of work along with a progress bar, see [`track`]
+///
+/// It dereferences to the wrapped source, so that the source can be inspected freely.
+/// Changes to the source should be made through [`with_mut`](Self::with_mut), or be
+/// followed by [`sync`](Self::sync), to keep the progress bar in step.
+pub struct Tracked<S> {
+    source: S,
+    bar: AvanceBar,
+}
+
+impl<S: ProgressSource> Tracked<S> {
+    /// Mutate the source, and then advance the progress bar accordingly.
+    pub fn with_mut<R>(&mut self, f: impl FnOnce(&mut S) -> R) -> R {
+        let r = f(&mut self.source);
+        self.sync();
+        r
+    }
+
+    /// Advance the progress bar to match the remaining count of the source.
+    ///
+    /// The progress bar never goes backwards, even if the remaining count grows.
+    pub fn sync(&self) {
+        let remaining = match self.source.remaining() {
+            Some(remaining) => remaining,
+            None => return,
+        };
+
+        let n = self.bar.update_and_get(0);
+        let total = match self.bar.total() {
+            Some(total) => total,
+            None => {
+                // The remaining count just becomes known
+                let total = n.saturating_add(remaining);
+                self.bar.set_total(Some(total));
+                total
+            }
+        };
+
+        let done = total.saturating_sub(remaining);
+        if done > n {
+            self.bar.update(done - n);
+        }
+    }
+
+    /// The progress bar of this source
+    pub fn pb(&self) -> &AvanceBar {
+        &self.bar
+    }
+
+    /// Close the progress bar, and give back the source
+    pub fn into_inner(self) -> S {
+        self.bar.close();
+        self.source
+    }
+}
+
+impl<S> Deref for Tracked<S> {
+    type Target = S;
+
+    fn deref(&self) -> &S {
+        &self.source
+    }
+}
+
+#[cfg(all(test, not(feature = "disabled")))]
+mod tests {
+    use crate::{track, ProgressSource};
+
+    struct Pages {
+        left: Option<u64>,
+    }
+
+    impl ProgressSource for Pages {
+        fn remaining(&self) -> Option<u64> {
+            self.left
+        }
+    }
+
+    #[test]
+    fn advance_with_remaining() {
+        let mut pages = track(Pages { left: Some(5) });
+        pages.with_mut(|p| p.left = Some(3));
+        assert_eq!(pages.pb().update_and_get(0), 2);
+
+        // Never goes backwards
+        pages.with_mut(|p| p.left = Some(4));
+        assert_eq!(pages.pb().update_and_get(0), 2);
+
+        pages.with_mut(|p| p.left = Some(0));
+        assert_eq!(pages.pb().update_and_get(0), 5);
+        assert_eq!(pages.into_inner().left, Some(0));
+    }
+
+    #[test]
+    fn remaining_known_later() {
+        let mut pages = track(Pages { left: None });
+        pages.pb().update(2);
+        assert!(pages.pb().total().is_none());
+
+        pages.with_mut(|p| p.left = Some(8));
+        assert_eq!(pages.pb().total(), Some(10));
+        pages.with_mut(|p| p.left = Some(1));
+        assert_eq!(pages.pb().update_and_get(0), 9);
+    }
+}
@@ -1,6 +1,12 @@
 //! Styles of a progress bar
 
 use std::borrow::Cow;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+use crossterm::style::Color;
+
+use crate::format::char_width;
 
 /// Styles of a progress bar
 #[derive(Debug, Clone, Default)]
@@ -19,6 +25,72 @@ pub enum Style {
     Custom(Cow<'static, str>),
 }
 
+impl Style {
+    /// Validate a user custom style, returning the matching pre-defined
+    /// style if the pattern is identical to one of them.
+    ///
+    /// A custom style string is like `{Finished}{Current}{ToDo}`, so it needs
+    /// at least three characters, and all of them should occupy the same
+    /// width on the terminal.
+    ///
+    /// # Examples
+    /// ```
+    /// # use avance::style::{Style, StyleError};
+    /// assert!(matches!(Style::try_from_str("=>-"), Ok(Style::Custom(_))));
+    /// assert!(matches!(Style::try_from_str("*.oO@ "), Ok(Style::Balloon)));
+    /// assert_eq!(Style::try_from_str("=-").unwrap_err(), StyleError::TooShort);
+    /// ```
+    pub fn try_from_str(s: impl Into<Cow<'static, str>>) -> Result<Self, StyleError> {
+        let s = s.into();
+
+        if s.chars().count() < 3 {
+            return Err(StyleError::TooShort);
+        }
+
+        let mut widths = s.chars().map(char_width);
+        let first = widths.next().unwrap();
+        if widths.any(|w| w != first) {
+            return Err(StyleError::InconsistentWidth);
+        }
+
+        let builtin = [Self::ASCII, Self::Block, Self::Balloon]
+            .into_iter()
+            .find(|style| style.as_ref() == s);
+
+        Ok(builtin.unwrap_or(Self::Custom(s)))
+    }
+}
+
+/// Pick [`Style::Block`] if the terminal is likely to render UTF-8, or
+/// [`Style::ASCII`] otherwise.
+///
+/// The locale is looked up from `LC_ALL`, `LC_CTYPE` and `LANG` in order, where the
+/// first non-empty one wins, as the C library does. Terminals on Windows are assumed
+/// to render UTF-8.
+///
+/// # Examples
+/// ```
+/// use avance::{detect_style, AvanceBar};
+///
+/// let pb = AvanceBar::new(100).with_style(detect_style());
+/// ```
+pub fn detect_style() -> Style {
+    let locale = ["LC_ALL", "LC_CTYPE", "LANG"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|locale| !locale.is_empty())
+        .unwrap_or_default();
+    style_for_locale(&locale)
+}
+
+fn style_for_locale(locale: &str) -> Style {
+    let locale = locale.to_ascii_lowercase();
+    match cfg!(windows) || locale.contains("utf-8") || locale.contains("utf8") {
+        true => Style::Block,
+        false => Style::ASCII,
+    }
+}
+
 impl AsRef<str> for Style {
     fn as_ref(&self) -> &str {
         match self {
@@ -29,3 +101,137 @@ impl AsRef<str> for Style {
         }
     }
 }
+
+/// Color themes of a progress bar
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Theme {
+    /// No colors
+    #[default]
+    Default,
+
+    /// Colors from the [Nord](https://www.nordtheme.com) palette
+    Nord,
+
+    /// Colors from the [Solarized](https://ethanschoonover.com/solarized) palette
+    Solarized,
+
+    /// Shades of grey
+    Monochrome,
+}
+
+/// Colors of the description and each segment of the bar
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Palette {
+    pub desc: Color,
+    pub filled: Color,
+    pub current: Color,
+    pub background: Color,
+}
+
+impl Theme {
+    pub(crate) fn palette(&self) -> Option<Palette> {
+        let rgb = |r, g, b| Color::Rgb { r, g, b };
+
+        match self {
+            Self::Default => None,
+            Self::Nord => Some(Palette {
+                desc: rgb(136, 192, 208),
+                filled: rgb(163, 190, 140),
+                current: rgb(235, 203, 139),
+                background: rgb(76, 86, 106),
+            }),
+            Self::Solarized => Some(Palette {
+                desc: rgb(38, 139, 210),
+                filled: rgb(133, 153, 0),
+                current: rgb(181, 137, 0),
+                background: rgb(88, 110, 117),
+            }),
+            Self::Monochrome => Some(Palette {
+                desc: Color::White,
+                filled: Color::White,
+                current: Color::Grey,
+                background: Color::DarkGrey,
+            }),
+        }
+    }
+}
+
+/// Where the percentage of a bounded bar sits relative to its description
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PercentPosition {
+    /// `73% task: |bar|`
+    BeforeDesc,
+
+    /// `task:  73%|bar|`
+    #[default]
+    AfterDesc,
+}
+
+/// Reasons why a user custom style is rejected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StyleError {
+    /// Fewer than three characters were given
+    TooShort,
+
+    /// Characters of the style have different display widths,
+    /// which would misalign the bar
+    InconsistentWidth,
+}
+
+impl Display for StyleError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TooShort => f.write_str("a style needs at least three characters"),
+            Self::InconsistentWidth => {
+                f.write_str("characters of a style must have the same display width")
+            }
+        }
+    }
+}
+
+impl Error for StyleError {}
+
+#[cfg(test)]
+mod tests {
+    use super::{style_for_locale, Style, StyleError};
+
+    #[test]
+    fn too_short() {
+        assert_eq!(Style::try_from_str("").unwrap_err(), StyleError::TooShort);
+        assert_eq!(Style::try_from_str("=>").unwrap_err(), StyleError::TooShort);
+    }
+
+    #[test]
+    fn inconsistent_width() {
+        assert_eq!(
+            Style::try_from_str("中>-").unwrap_err(),
+            StyleError::InconsistentWidth
+        );
+        assert!(Style::try_from_str("中文字").is_ok());
+    }
+
+    #[test]
+    #[cfg(not(windows))]
+    fn detect_style_from_locale() {
+        assert!(matches!(style_for_locale(""), Style::ASCII));
+        assert!(matches!(style_for_locale("C"), Style::ASCII));
+        assert!(matches!(style_for_locale("en_US.UTF-8"), Style::Block));
+        assert!(matches!(style_for_locale("zh_CN.utf8"), Style::Block));
+    }
+
+    #[test]
+    fn owned_custom_style() {
+        let computed = ["=", ">", "-"].concat();
+        let style = Style::try_from_str(computed.clone()).unwrap();
+        assert_eq!(style.as_ref(), computed);
+    }
+
+    #[test]
+    fn builtin_detection() {
+        assert!(matches!(
+            Style::try_from_str("#0123456789 "),
+            Ok(Style::ASCII)
+        ));
+        assert!(matches!(Style::try_from_str("=>-"), Ok(Style::Custom(_))));
+    }
+}
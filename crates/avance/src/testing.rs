@@ -0,0 +1,132 @@
+//! Capture the output of progress bars, which helps test the progress reporting
+//! of downstream crates
+
+use std::cell::RefCell;
+use std::io::{stderr, Result, Write};
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Where the output of a capture goes
+pub(crate) type Sink = Arc<Mutex<Vec<u8>>>;
+
+thread_local! {
+    // The sink of the ongoing capture in this thread
+    static SINK: RefCell<Option<Sink>> = const { RefCell::new(None) };
+}
+
+/// Run a closure, and return the output of progress bars in the meantime
+/// instead of writing it to stderr.
+///
+/// Bars are captured as if stderr weren't a terminal, so only the plain lines
+/// of the dumb mode (see [`set_dumb_step`](crate::set_dumb_step)) are captured.
+/// The output of bars created in the closure is captured even if they're drawn
+/// from other threads, along with everything else printed by the current thread.
+/// Bars of other threads are not affected, so captures can run in parallel.
+///
+/// # Examples
+/// ```
+/// use avance::{testing, AvanceBar};
+///
+/// let output = testing::capture(|| {
+///     let pb = AvanceBar::new(10).with_desc("job");
+///     pb.update(10);
+/// });
+/// # #[cfg(not(feature = "disabled"))]
+/// assert!(output.contains("job: 100%|"));
+/// ```
+pub fn capture<F: FnOnce()>(f: F) -> String {
+    // Restore the outer capture (if any) even if the closure panics
+    struct Guard(Option<Sink>);
+    impl Drop for Guard {
+        fn drop(&mut self) {
+            SINK.with(|sink| *sink.borrow_mut() = self.0.take());
+        }
+    }
+
+    let captured = Sink::default();
+    let outer = SINK.with(|sink| sink.borrow_mut().replace(Arc::clone(&captured)));
+    let guard = Guard(outer);
+    f();
+    drop(guard);
+
+    let captured = std::mem::take(&mut *captured.lock().unwrap_or_else(PoisonError::into_inner));
+    String::from_utf8_lossy(&captured).into_owned()
+}
+
+/// The sink of the ongoing capture in the current thread
+pub(crate) fn sink() -> Option<Sink> {
+    SINK.with(|sink| sink.borrow().clone())
+}
+
+/// Stderr, or the buffer of a capture. Writes are buffered until flushed (or
+/// dropped), so that lines printed by different threads never interleave.
+pub(crate) struct Output {
+    sink: Option<Sink>,
+    buffer: Vec<u8>,
+}
+
+impl Output {
+    pub(crate) fn new(sink: Option<Sink>) -> Self {
+        Self {
+            sink,
+            buffer: Vec::new(),
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let buffer = std::mem::take(&mut self.buffer);
+        match &self.sink {
+            Some(sink) => {
+                let mut sink = sink.lock().unwrap_or_else(PoisonError::into_inner);
+                sink.extend_from_slice(&buffer);
+                Ok(())
+            }
+            None => {
+                let mut stderr = stderr().lock();
+                stderr.write_all(&buffer)?;
+                stderr.flush()
+            }
+        }
+    }
+}
+
+impl Drop for Output {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+#[cfg(all(test, not(feature = "disabled")))]
+mod tests {
+    use super::capture;
+    use crate::bar::print_plain_lines;
+    use crate::AvanceBar;
+
+    #[test]
+    fn capture_closing_lines() {
+        let _lines = print_plain_lines();
+        let output = capture(|| {
+            let pb = AvanceBar::new(100)
+                .with_desc("captured")
+                .with_refresh_on_inc(true);
+            pb.update(30);
+            std::thread::scope(|t| {
+                t.spawn(|| pb.update(70));
+            });
+        });
+        assert!(output.contains("captured:  30%|"));
+        assert!(output.contains("captured: 100%|"));
+
+        let output = capture(|| {});
+        assert!(!output.contains("captured: "));
+    }
+}